@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+
+use sigmar::{Board, Move, TOTAL_MOVES};
+
+/// Explores every position reachable from a board, unlike `Board::solve` which stops at the first
+/// solution. Positions are memoized by their zobrist hash.
+///
+/// Full deals can have far more positions than fit in memory, so exploration stops after
+/// `max_positions`. Statistics of a search that stopped early are bounds, see `SolveStats`.
+pub struct Analysis {
+    memo: HashMap<u64, Node>,
+    solution_cap: usize,
+    max_positions: usize,
+    complete: bool,
+}
+
+struct Node {
+    /// Hashes of the distinct move sets that clear the board from here, at most `solution_cap`.
+    /// Empty if the position can't be solved.
+    completions: Vec<u64>,
+    /// Fewest salt-on-element moves any solution from here needs.
+    min_salt: usize,
+    /// Legal moves that lead into an unsolvable position.
+    dead_ends: usize,
+    legal_moves: usize,
+    /// Moves made since the analysed position.
+    depth: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SolveStats {
+    /// Whether every reachable position was explored. If not, `solutions`, `dead_ends` and
+    /// `solvable_positions` are lower bounds and `forced_salt` an upper bound.
+    pub complete: bool,
+    /// Positions expanded.
+    pub positions: usize,
+    pub solvable_positions: usize,
+    /// Solutions up to move reordering, capped at the analysis' solution cap.
+    pub solutions: usize,
+    /// Moves from a solvable position into an unsolvable one.
+    pub dead_ends: usize,
+    /// Salt-on-element moves that every solution has to make.
+    pub forced_salt: usize,
+    pub first_moves: usize,
+    /// First moves that lose the game.
+    pub first_move_dead_ends: usize,
+    /// Average number of legal moves in the solvable positions.
+    pub mean_legal_moves: f32,
+    /// Fewest moves after which a legal move can lose the game, `None` if no move can.
+    pub first_trap: Option<usize>,
+}
+
+/// How hard a deal is to solve by hand, and what the rating is made of.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Difficulty {
+    /// Roughly 0 for a deal where nothing can go wrong to 10 for the nastiest deals. Infinite if no
+    /// solution was found.
+    pub rating: f32,
+    /// Share of the first moves that lose the game.
+    pub first_move_dead_ends: f32,
+    pub mean_legal_moves: f32,
+    /// Solutions up to move reordering, at most `DIFFICULTY_SOLUTION_CAP`.
+    pub solutions: usize,
+    pub first_trap: Option<usize>,
+    pub stats: SolveStats,
+}
+
+pub const DIFFICULTY_SOLUTION_CAP: usize = 16;
+pub const DIFFICULTY_MAX_POSITIONS: usize = 200_000;
+
+impl Difficulty {
+    pub fn from_stats(stats: &SolveStats) -> Difficulty {
+        let first_move_dead_ends = stats.first_move_dead_ends as f32 / stats.first_moves.max(1) as f32;
+        let solutions = stats.solutions.min(DIFFICULTY_SOLUTION_CAP);
+
+        let rating = if solutions == 0 {
+            ::std::f32::INFINITY
+        } else {
+            let traps = stats.dead_ends as f32 / stats.solvable_positions.max(1) as f32;
+            let scarcity = 1.0 - (solutions as f32).ln() / (DIFFICULTY_SOLUTION_CAP as f32).ln();
+            let earliness = match stats.first_trap {
+                Some(depth) => 1.0 - depth as f32 / TOTAL_MOVES as f32,
+                None => 0.0,
+            };
+            3.0 * first_move_dead_ends.min(1.0) + 2.0 * (traps / 2.0).min(1.0) + 2.0 * scarcity
+                + 2.0 * earliness + (stats.forced_salt as f32 / 2.0).min(1.0)
+        };
+
+        Difficulty {
+            rating, first_move_dead_ends, solutions,
+            mean_legal_moves: stats.mean_legal_moves,
+            first_trap: stats.first_trap,
+            stats: *stats,
+        }
+    }
+}
+
+impl Analysis {
+    pub fn new(solution_cap: usize, max_positions: usize) -> Analysis {
+        Analysis {
+            memo: HashMap::with_capacity(60_000),
+            solution_cap, max_positions,
+            complete: true,
+        }
+    }
+
+    pub fn run(&mut self, board: &Board) -> SolveStats {
+        let mut board = board.clone();
+        self.explore(&mut board, 0);
+
+        let solvable: Vec<&Node> = self.memo.values().filter(|n| !n.completions.is_empty()).collect();
+        let root = self.memo.get(&board.hash());
+        let (solutions, forced_salt) = match root {
+            Some(root) if !root.completions.is_empty() => (root.completions.len(), root.min_salt),
+            _ => (0, 0),
+        };
+        let legal_moves: usize = solvable.iter().map(|n| n.legal_moves).sum();
+
+        SolveStats {
+            complete: self.complete,
+            positions: self.memo.len(),
+            solvable_positions: solvable.len(),
+            solutions,
+            dead_ends: solvable.iter().map(|n| n.dead_ends).sum(),
+            forced_salt,
+            first_moves: root.map_or(0, |n| n.legal_moves),
+            first_move_dead_ends: root.map_or(0, |n| n.dead_ends),
+            mean_legal_moves: legal_moves as f32 / solvable.len().max(1) as f32,
+            first_trap: solvable.iter().filter(|n| n.dead_ends > 0).map(|n| n.depth).min(),
+        }
+    }
+
+    /// Whether the position can be solved, `None` if the position budget ran out before finding out.
+    fn explore(&mut self, board: &mut Board, depth: usize) -> Option<bool> {
+        if let Some(node) = self.memo.get(&board.hash()) {
+            return Some(!node.completions.is_empty());
+        }
+        if self.memo.len() >= self.max_positions {
+            self.complete = false;
+            return None;
+        }
+
+        let legal = board.legal_moves();
+        let mut node = Node {
+            completions: Vec::new(),
+            min_salt: usize::max_value(),
+            dead_ends: 0,
+            legal_moves: legal.len(),
+            depth,
+        };
+        if board.marble_count() == 0 {
+            node.completions.push(0);
+            node.min_salt = 0;
+        }
+
+        let mut unknown = false;
+        for m in legal {
+            board.make_move(m);
+            match self.explore(board, depth + 1) {
+                Some(true) => {
+                    let child = &self.memo[&board.hash()];
+                    for &c in &child.completions {
+                        let set = c ^ move_hash(m);
+                        if node.completions.len() < self.solution_cap && !node.completions.contains(&set) {
+                            node.completions.push(set);
+                        }
+                    }
+                    let salt = if m.uses_salt_on_element() { 1 } else { 0 };
+                    if child.min_salt + salt < node.min_salt {
+                        node.min_salt = child.min_salt + salt;
+                    }
+                },
+                Some(false) => node.dead_ends += 1,
+                None => unknown = true,
+            }
+            board.reverse_move(m);
+        }
+
+        let solvable = !node.completions.is_empty();
+        if unknown && !solvable {
+            // Can't tell this apart from a dead end, so don't remember it as one.
+            return None;
+        }
+        self.memo.insert(board.hash(), node);
+        Some(solvable)
+    }
+}
+
+/// Order independent hash of a move, so that a set of moves can be hashed by xoring its members.
+fn move_hash(m: Move) -> u64 {
+    let (a, b) = ((m.a.y * 13 + m.a.x) as u64, (m.b.y * 13 + m.b.x) as u64);
+    let (lo, hi) = if a < b { (a, b) } else { (b, a) };
+    // splitmix64 finalizer
+    let mut z = (hi << 8 | lo).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
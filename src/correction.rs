@@ -0,0 +1,251 @@
+use ocr::{BoardRead, Recognition, LOW_CONFIDENCE};
+use sigmar::{Board, Marble, DEAL_COUNTS};
+use solver::{hopeless, Dfs, Solver};
+
+/// Cells relabelled to make a read consistent.
+pub struct Correction {
+    pub board: Board,
+    /// Cell by array indices, what it was read as and what it was changed to.
+    pub changed: Vec<((usize, usize), Marble, Marble)>,
+    /// Sum over the changed cells of how far their new label's score is behind the best one, relative
+    /// to the best.
+    pub cost: f32,
+    /// Cells of `BoardRead::low_confidence` left as they were read that the counts don't back up
+    /// either: something else the cell could be would fit as well. The board isn't safe to play while
+    /// there are any.
+    pub unconfirmed: Vec<(usize, usize)>,
+}
+
+/// Alternatives considered for an ambiguous cell, best first.
+const CANDIDATES: usize = 4;
+/// Consistent labellings kept, cheapest first, for the solvability check.
+const KEEP: usize = 20;
+/// Ambiguous cells are tried in growing sets, up to this many.
+const MAX_AMBIGUOUS: usize = 16;
+/// Partial labellings a search over one set of ambiguous cells may visit.
+const SEARCH_NODES: usize = 1_000_000;
+/// Positions the solvability check may expand per labelling.
+const SOLVE_NODES: usize = 200_000;
+
+fn deal_total() -> usize {
+    DEAL_COUNTS.iter().map(|&(_, n)| n).sum()
+}
+
+/// Whether the counts could be on the board: those of a whole deal if there are as many marbles as in
+/// one, otherwise no more than a deal has, and not ruled out by `hopeless`.
+pub fn consistent(board: &Board) -> bool {
+    let counts = counts(board);
+    let full = counts.iter().sum::<usize>() == deal_total();
+    let fits = DEAL_COUNTS.iter().all(|&(m, n)| if full { counts[m as usize] == n } else { counts[m as usize] <= n });
+    fits && !hopeless(board)
+}
+
+fn counts(board: &Board) -> [usize; 14] {
+    let mut counts = [0; 14];
+    for row in board.board.iter() {
+        for &m in row.iter() {
+            if m != Marble::Empty { counts[m as usize] += 1 }
+        }
+    }
+    counts
+}
+
+fn cost(r: &Recognition, i: usize) -> f32 {
+    let best = r.scores[0].1;
+    if best > 0.0 { (best - r.scores[i].1) / best } else { 0.0 }
+}
+
+/// The cheapest relabelling of a read whose marble counts are consistent, see `consistent`. Occupied
+/// cells stay occupied. The least confident cells are searched over their best few glyphs, taking
+/// more of them until some labelling fits. With `require_solvable`, a labelling also has to be solved
+/// by a budgeted search. `None` if nothing within the limits works.
+pub fn correct(read: &BoardRead, require_solvable: bool) -> Option<Correction> {
+    let mut ambiguous: Vec<&((usize, usize), Recognition)> = read.cells.iter()
+        .filter(|&&(_, ref r)| r.marble() != Marble::Empty && r.scores.len() > 1)
+        .collect();
+    ambiguous.sort_by(|a, b| a.1.certainty().partial_cmp(&b.1.certainty()).unwrap());
+
+    let solvable = |board: &Board| {
+        let mut dfs = Dfs::new(None);
+        dfs.max_nodes = SOLVE_NODES;
+        dfs.solve(board).is_some()
+    };
+    let accept = |board: &Board| consistent(board) && (!require_solvable || solvable(board));
+    if accept(&read.board) {
+        let unconfirmed = unconfirmed(read, &read.board, &[]);
+        return Some(Correction { board: read.board.clone(), changed: Vec::new(), cost: 0.0, unconfirmed });
+    }
+
+    let full = read.cells.iter().filter(|&&(_, ref r)| r.marble() != Marble::Empty).count() == deal_total();
+    let mut size = 1;
+    while size <= MAX_AMBIGUOUS && size / 2 < ambiguous.len() {
+        let cells = &ambiguous[..std::cmp::min(size, ambiguous.len())];
+        let mut search = Search { cells, full, found: Vec::new(), budget: SEARCH_NODES };
+        let mut board = read.board.clone();
+        for &&((x, y), _) in cells {
+            board.board[y][x] = Marble::Empty;
+        }
+        search.run(&mut board, 0, 0.0, &mut Vec::new());
+
+        for (cost, choice) in search.found {
+            let mut board = read.board.clone();
+            let mut changed = Vec::new();
+            for (&&((x, y), ref r), &i) in cells.iter().zip(choice.iter()) {
+                board.board[y][x] = r.scores[i].0;
+                if i != 0 { changed.push(((x, y), r.scores[0].0, r.scores[i].0)) }
+            }
+            if !require_solvable || solvable(&board) {
+                let unconfirmed = unconfirmed(read, &board, &changed);
+                return Some(Correction { board, changed, cost, unconfirmed });
+            }
+        }
+        size *= 2;
+    }
+    None
+}
+
+/// The low confidence cells of the read that weren't changed and could be read differently with the
+/// counts still consistent: empty for marbles whose occupancy is in doubt, their next best glyphs for
+/// those whose symbol is.
+fn unconfirmed(read: &BoardRead, board: &Board, changed: &[((usize, usize), Marble, Marble)]) -> Vec<(usize, usize)> {
+    read.cells.iter().filter(|&&(pos, ref r)| {
+        if !read.low_confidence.contains(&pos) || changed.iter().any(|c| c.0 == pos) { return false }
+        let mut alternatives = Vec::new();
+        if r.occupancy.confidence < LOW_CONFIDENCE {
+            alternatives.push(if r.occupancy.occupied { Marble::Empty } else { r.scores.first().map_or(Marble::Empty, |s| s.0) });
+        }
+        if r.occupancy.occupied && r.confidence < LOW_CONFIDENCE {
+            alternatives.extend(r.scores.iter().skip(1).take(CANDIDATES - 1).map(|s| s.0));
+        }
+        let (x, y) = pos;
+        alternatives.into_iter().filter(|&m| m != board.board[y][x]).any(|m| {
+            let mut other = board.clone();
+            other.board[y][x] = m;
+            consistent(&other)
+        })
+    }).map(|&(pos, _)| pos).collect()
+}
+
+/// Branch and bound over labellings of some cells, keeping the `KEEP` cheapest that make the counts
+/// consistent.
+struct Search<'a> {
+    cells: &'a [&'a ((usize, usize), Recognition)],
+    /// Whether the board is a whole deal, so the counts have to come out exact.
+    full: bool,
+    /// Cost and index into each cell's scores, cheapest first.
+    found: Vec<(f32, Vec<usize>)>,
+    budget: usize,
+}
+
+impl<'a> Search<'a> {
+    /// Labels the cells from `depth` on. The board has them empty to begin with.
+    fn run(&mut self, board: &mut Board, depth: usize, cost_so_far: f32, choice: &mut Vec<usize>) {
+        if self.budget == 0 { return }
+        self.budget -= 1;
+        if self.found.len() == KEEP && cost_so_far >= self.found[KEEP - 1].0 { return }
+
+        let counts = counts(board);
+        let over = DEAL_COUNTS.iter().any(|&(m, n)| counts[m as usize] > n);
+        let missing: usize = DEAL_COUNTS.iter().map(|&(m, n)| n.saturating_sub(counts[m as usize])).sum();
+        if over || (self.full && missing > self.cells.len() - depth) { return }
+
+        if depth == self.cells.len() {
+            if consistent(board) {
+                let at = self.found.iter().position(|f| f.0 > cost_so_far).unwrap_or(self.found.len());
+                self.found.insert(at, (cost_so_far, choice.clone()));
+                self.found.truncate(KEEP);
+            }
+            return;
+        }
+
+        let &((x, y), ref r) = self.cells[depth];
+        for i in 0..std::cmp::min(CANDIDATES, r.scores.len()) {
+            board.board[y][x] = r.scores[i].0;
+            choice.push(i);
+            self.run(board, depth + 1, cost_so_far + cost(r, i), choice);
+            choice.pop();
+        }
+        board.board[y][x] = Marble::Empty;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, XorShiftRng};
+    use ::correction::*;
+    use ::designer::random_deal;
+    use ::ocr::{BoardRead, Occupancy};
+    use ::sigmar::{board_cells, Marble};
+
+    #[test]
+    fn test_swapped_cells_are_corrected() {
+        let deal = random_deal(&mut XorShiftRng::from_seed([1, 2, 3, 4]));
+        let cells: Vec<(usize, usize)> = board_cells().into_iter()
+            .filter(|&(x, y)| deal.board[y][x] != Marble::Empty)
+            .collect();
+        let fire = cells.iter().cloned().find(|&(x, y)| deal.board[y][x] == Marble::Fire).unwrap();
+        let salt = cells.iter().cloned().find(|&(x, y)| deal.board[y][x] == Marble::Salt).unwrap();
+
+        // Every cell read right and sure, except the fire read as water by a hair, and a salt that
+        // looks like air.
+        let recognitions = cells.iter().map(|&(x, y)| {
+            let truth = deal.board[y][x];
+            let scores = if (x, y) == fire {
+                vec![(Marble::Water, 100.0), (Marble::Fire, 95.0), (Marble::Air, 40.0)]
+            } else if (x, y) == salt {
+                vec![(Marble::Air, 100.0), (Marble::Salt, 90.0), (Marble::Earth, 30.0)]
+            } else {
+                vec![(truth, 100.0), (Marble::Mercury, 20.0)]
+            };
+            let occupancy = Occupancy { occupied: true, confidence: 1.0, evidence: 4.0 };
+            ((x, y), Recognition::from_scores(occupancy, scores))
+        }).collect();
+
+        let mut board = deal.clone();
+        board.board[fire.1][fire.0] = Marble::Water;
+        board.board[salt.1][salt.0] = Marble::Air;
+        let read = BoardRead::new(board, recognitions, &[0.0; 14]);
+
+        let correction = correct(&read, false).unwrap();
+        assert_eq!(2, correction.changed.len());
+        for &(x, y) in &cells {
+            assert_eq!(deal.board[y][x], correction.board.board[y][x]);
+        }
+    }
+
+    #[test]
+    fn test_unsure_cells_need_the_counts_behind_them() {
+        let deal = random_deal(&mut XorShiftRng::from_seed([1, 2, 3, 4]));
+        let of = |marble: Marble| board_cells().into_iter().filter(|&(x, y)| deal.board[y][x] == marble).collect::<Vec<_>>();
+        let (fires, waters) = (of(Marble::Fire), of(Marble::Water));
+        let unsure = fires[0];
+        let read = |missing: &[(usize, usize)]| {
+            let mut board = deal.clone();
+            let recognitions = board_cells().into_iter()
+                .filter(|&(x, y)| deal.board[y][x] != Marble::Empty && !missing.contains(&(x, y)))
+                .map(|(x, y)| {
+                    let scores = if (x, y) == unsure {
+                        vec![(Marble::Fire, 100.0), (Marble::Water, 95.0)]
+                    } else {
+                        vec![(deal.board[y][x], 100.0), (Marble::Mercury, 20.0)]
+                    };
+                    let occupancy = Occupancy { occupied: true, confidence: 1.0, evidence: 4.0 };
+                    ((x, y), Recognition::from_scores(occupancy, scores))
+                }).collect();
+            for &(x, y) in missing {
+                board.board[y][x] = Marble::Empty;
+            }
+            BoardRead::new(board, recognitions, &[0.0; 14])
+        };
+
+        // A whole deal has exactly as many fire as it has, so the fire can't be water.
+        let whole = read(&[]);
+        assert_eq!(vec![unsure], whole.low_confidence);
+        let correction = correct(&whole, false).unwrap();
+        assert!(correction.changed.is_empty() && correction.unconfirmed.is_empty());
+
+        // With a fire and a water gone, it could be either.
+        let correction = correct(&read(&[fires[1], waters[0]]), false).unwrap();
+        assert_eq!(vec![unsure], correction.unconfirmed);
+    }
+}
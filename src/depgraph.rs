@@ -0,0 +1,161 @@
+use rand::Rng;
+
+use sigmar::{Marble, MarblePos, Move, NEIGHBOURS};
+
+/// Which moves of a solution have to come before which. Move B depends on an earlier move A if A
+/// removes a neighbour of a marble B uses, so A may be what frees it, or if A removes a metal lower
+/// in the chain than the one B needs.
+///
+/// Neighbouring doesn't mean A actually was needed to free B, so the order is stricter than it has
+/// to be, but any order that respects it is a valid solution: the board around each move can only
+/// be emptier than it was in the original solution.
+#[derive(Debug, Clone)]
+pub struct DependencyGraph {
+    pub moves: Vec<Move>,
+    /// For each move, the earlier moves it depends on directly.
+    deps: Vec<Vec<usize>>,
+}
+
+fn metal_rank(m: Move) -> Option<usize> {
+    [m.a.marble, m.b.marble].iter()
+        .map(|&marble| marble as usize)
+        .filter(|&rank| rank <= Marble::Gold as usize)
+        .next()
+}
+
+fn marbles(m: Move) -> Vec<MarblePos> {
+    if m.a == m.b { vec![m.a] } else { vec![m.a, m.b] }
+}
+
+fn adjacent(p: MarblePos, q: MarblePos) -> bool {
+    NEIGHBOURS.iter().any(|&(dx, dy)| p.x as i32 + dx == q.x as i32 && p.y as i32 + dy == q.y as i32)
+}
+
+impl DependencyGraph {
+    /// Graph of a solution, as returned by `Board::solve`.
+    pub fn new(solution: &[Move]) -> DependencyGraph {
+        let deps = (0..solution.len()).map(|j| {
+            let b = solution[j];
+            (0..j).filter(|&i| {
+                let a = solution[i];
+                let frees = marbles(a).iter().any(|&p| marbles(b).iter().any(|&q| adjacent(p, q)));
+                let chain = match (metal_rank(a), metal_rank(b)) {
+                    (Some(ra), Some(rb)) => ra < rb,
+                    _ => false,
+                };
+                frees || chain
+            }).collect()
+        }).collect();
+        DependencyGraph { moves: solution.to_vec(), deps }
+    }
+
+    pub fn len(&self) -> usize {
+        self.moves.len()
+    }
+
+    pub fn dependencies(&self, i: usize) -> &[usize] {
+        &self.deps[i]
+    }
+
+    pub fn depends_on(&self, b: usize, a: usize) -> bool {
+        self.deps[b].contains(&a)
+    }
+
+    /// Whether `order`, as indices into `moves`, lists every move once and after its dependencies.
+    pub fn is_valid_order(&self, order: &[usize]) -> bool {
+        let mut done = vec![false; self.len()];
+        for &i in order {
+            if i >= self.len() || done[i] || self.deps[i].iter().any(|&d| !done[d]) { return false }
+            done[i] = true;
+        }
+        order.len() == self.len()
+    }
+
+    pub fn reorder(&self, order: &[usize]) -> Vec<Move> {
+        order.iter().map(|&i| self.moves[i]).collect()
+    }
+
+    /// Moves that can go next once the moves marked done are made.
+    pub fn ready(&self, done: &[bool]) -> Vec<usize> {
+        (0..self.len()).filter(|&i| !done[i] && self.deps[i].iter().all(|&d| done[d])).collect()
+    }
+
+    /// Up to `limit` valid orders, in lexicographic order of move indices.
+    pub fn linearizations(&self, limit: usize) -> Vec<Vec<usize>> {
+        let mut found = Vec::new();
+        let mut done = vec![false; self.len()];
+        let mut order = Vec::with_capacity(self.len());
+        self.extend(&mut done, &mut order, limit, &mut found);
+        found
+    }
+
+    fn extend(&self, done: &mut Vec<bool>, order: &mut Vec<usize>, limit: usize, found: &mut Vec<Vec<usize>>) {
+        if found.len() >= limit { return }
+        if order.len() == self.len() {
+            found.push(order.clone());
+            return;
+        }
+        for i in self.ready(done) {
+            done[i] = true;
+            order.push(i);
+            self.extend(done, order, limit, found);
+            order.pop();
+            done[i] = false;
+        }
+    }
+
+    /// A valid order picked by choosing uniformly among the ready moves at each step. Orders aren't
+    /// all equally likely.
+    pub fn sample_linearization<R: Rng>(&self, rng: &mut R) -> Vec<usize> {
+        let mut done = vec![false; self.len()];
+        let mut order = Vec::with_capacity(self.len());
+        while order.len() < self.len() {
+            let &i = rng.choose(&self.ready(&done)).unwrap();
+            done[i] = true;
+            order.push(i);
+        }
+        order
+    }
+
+    /// Whether `other` makes the same moves, in whatever order.
+    pub fn same_moves(&self, other: &[Move]) -> bool {
+        let key = |m: &Move| {
+            let (a, b) = ((m.a.x, m.a.y), (m.b.x, m.b.y));
+            if a < b { (a, b) } else { (b, a) }
+        };
+        let mut mine: Vec<_> = self.moves.iter().map(&key).collect();
+        let mut theirs: Vec<_> = other.iter().map(&key).collect();
+        mine.sort();
+        theirs.sort();
+        mine == theirs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, XorShiftRng};
+    use ::designer::solved_deal;
+    use ::depgraph::*;
+    use ::solver::is_solution;
+
+    #[test]
+    fn test_reorderings_are_solutions() {
+        let (board, solution) = solved_deal(1);
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let graph = DependencyGraph::new(&solution);
+
+        let identity: Vec<usize> = (0..graph.len()).collect();
+        assert!(graph.is_valid_order(&identity));
+
+        for order in graph.linearizations(20) {
+            assert!(is_solution(&board, &graph.reorder(&order)));
+        }
+        for _ in 0..20 {
+            let order = graph.sample_linearization(&mut rng);
+            assert!(graph.is_valid_order(&order));
+            let moves = graph.reorder(&order);
+            assert!(is_solution(&board, &moves));
+            assert!(graph.same_moves(&moves));
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use std;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use rand::{thread_rng, Rng};
+
+use analysis::{Analysis, Difficulty, SolveStats, DIFFICULTY_SOLUTION_CAP};
+use sigmar::{board_cells, Board, Marble, MarblePos, DEAL_COUNTS, NEIGHBOURS};
+
+/// Properties a designed deal has to have.
+#[derive(Debug, Clone, Copy)]
+pub struct Requirements {
+    /// Exactly one solution up to move reordering.
+    pub unique: bool,
+    pub min_dead_ends: usize,
+    pub min_forced_salt: usize,
+    /// Positions explored per deal. Uniqueness and forced salt can only be confirmed for deals
+    /// that are fully explored within this.
+    pub max_positions: usize,
+}
+
+impl Default for Requirements {
+    fn default() -> Requirements {
+        Requirements { unique: false, min_dead_ends: 0, min_forced_salt: 0, max_positions: 200_000 }
+    }
+}
+
+impl Requirements {
+    fn accepts(&self, stats: &SolveStats) -> bool {
+        stats.solutions > 0
+            && (!self.unique || (stats.complete && stats.solutions == 1))
+            && stats.dead_ends >= self.min_dead_ends
+            && (self.min_forced_salt == 0 || (stats.complete && stats.forced_salt >= self.min_forced_salt))
+    }
+}
+
+pub struct Design {
+    pub board: Board,
+    pub difficulty: Difficulty,
+}
+
+/// Generates random deals until one meets the requirements, giving up after `attempts` deals.
+pub fn design<R: Rng>(req: &Requirements, attempts: usize, rng: &mut R) -> Option<Design> {
+    for _ in 0..attempts {
+        let board = random_deal(rng);
+        let stats = Analysis::new(DIFFICULTY_SOLUTION_CAP, req.max_positions).run(&board);
+        if req.accepts(&stats) {
+            let difficulty = Difficulty::from_stats(&stats);
+            return Some(Design { board, difficulty });
+        }
+    }
+    None
+}
+
+/// A solvable deal with the standard marble counts. The deal is built by playing the game backwards
+/// from an empty board: each pair is put down only where both marbles are free, so removing the pairs
+/// in reverse order solves it.
+pub fn random_deal<R: Rng>(rng: &mut R) -> Board {
+    loop {
+        if let Some(board) = try_random_deal(rng) {
+            return board;
+        }
+    }
+}
+
+/// The first deal from the seed that a budgeted search solves, with the solution, for tests that need
+/// both. The same seed always gives the same deal.
+#[cfg(test)]
+pub fn solved_deal(seed: u32) -> (Board, Vec<::sigmar::Move>) {
+    use rand::{SeedableRng, XorShiftRng};
+    use solver::{Dfs, Solver};
+
+    let mut rng = XorShiftRng::from_seed([seed, 1, 2, 3]);
+    let mut dfs = Dfs::new(None);
+    dfs.max_nodes = 20_000;
+    loop {
+        let board = random_deal(&mut rng);
+        if let Some(solution) = dfs.solve(&board) {
+            return (board, solution);
+        }
+    }
+}
+
+fn try_random_deal<R: Rng>(rng: &mut R) -> Option<Board> {
+    let mut board = Board::offline([[Marble::Empty; 13]; 13]);
+    board.add_marble(MarblePos { x: 6, y: 6, marble: Marble::Gold });
+
+    for (a, b) in deal_pairs(rng)? {
+        let mut placed = false;
+        for _ in 0..20 {
+            let pos_a = random_free_cell(&board, a, rng)?;
+            board.add_marble(pos_a);
+            if let Some(pos_b) = random_free_cell(&board, b, rng) {
+                board.add_marble(pos_b);
+                if board.is_free(pos_a.x, pos_a.y) {
+                    placed = true;
+                    break;
+                }
+                board.remove_marble(pos_b);
+            }
+            board.remove_marble(pos_a);
+        }
+        if !placed { return None }
+    }
+    Some(board)
+}
+
+/// Empty cell chosen at random where `marble` would be free. Cells touching more marbles are
+/// preferred, which keeps the deal as compact as the ones the game makes.
+fn random_free_cell<R: Rng>(board: &Board, marble: Marble, rng: &mut R) -> Option<MarblePos> {
+    let mut probe = board.clone();
+    let candidates: Vec<(MarblePos, usize)> = board_cells().into_iter()
+        .filter(|&(x, y)| board.board[y][x] == Marble::Empty)
+        .map(|(x, y)| MarblePos { x, y, marble })
+        .filter(|&pos| {
+            probe.add_marble(pos);
+            let free = probe.is_free(pos.x, pos.y);
+            probe.remove_marble(pos);
+            free
+        })
+        .map(|pos| {
+            let neighbours = NEIGHBOURS.iter()
+                .filter(|&&(dx, dy)| {
+                    let (x, y) = ((pos.x as i32 + dx) as usize, (pos.y as i32 + dy) as usize);
+                    board.board[y][x] != Marble::Empty
+                })
+                .count();
+            (pos, 1 + neighbours * neighbours * neighbours)
+        })
+        .collect();
+
+    let total: usize = candidates.iter().map(|&(_, weight)| weight).sum();
+    if total == 0 { return None }
+    let mut pick = rng.gen_range(0, total);
+    for &(pos, weight) in &candidates {
+        if pick < weight { return Some(pos) }
+        pick -= weight;
+    }
+    None
+}
+
+/// The pairs of a deal other than gold, in the order they are put on the board.
+fn deal_pairs<R: Rng>(rng: &mut R) -> Option<Vec<(Marble, Marble)>> {
+    let count = |marble: Marble| DEAL_COUNTS.iter().find(|&&(m, _)| m == marble).unwrap().1;
+
+    let mut elementals: Vec<Marble> = Vec::with_capacity(36);
+    for &marble in &[Marble::Air, Marble::Fire, Marble::Water, Marble::Earth, Marble::Salt] {
+        elementals.extend(std::iter::repeat(marble).take(count(marble)));
+    }
+    rng.shuffle(&mut elementals);
+
+    let mut pairs: Vec<(Marble, Marble)> = Vec::with_capacity(27);
+    while let Some(a) = elementals.pop() {
+        let partners: Vec<usize> = (0..elementals.len())
+            .filter(|&i| elementals[i] == a || elementals[i] == Marble::Salt || a == Marble::Salt)
+            .collect();
+        let &i = rng.choose(&partners)?;
+        pairs.push((a, elementals.swap_remove(i)));
+    }
+    for _ in 0..count(Marble::Vitae) {
+        pairs.push((Marble::Vitae, Marble::Mors));
+    }
+    // Metal placeholders, given their actual metal after shuffling.
+    for _ in 0..count(Marble::Mercury) {
+        pairs.push((Marble::Mercury, Marble::Empty));
+    }
+    rng.shuffle(&mut pairs);
+
+    // Silver is removed right before gold, so it goes on the board first.
+    let mut metals = [Marble::Silver, Marble::Copper, Marble::Iron, Marble::Tin, Marble::Lead].iter();
+    for pair in &mut pairs {
+        if pair.1 == Marble::Empty {
+            pair.1 = *metals.next().unwrap();
+        }
+    }
+    Some(pairs)
+}
+
+fn usage() -> ! {
+    println!("usage: sigmar_bot design <count> [--unique] [--min-dead-ends N] [--min-salt N] [--max-positions N] [--out DIR]");
+    std::process::exit(1);
+}
+
+fn parse_value(arg: Option<&String>) -> usize {
+    match arg.map(|a| a.parse()) {
+        Some(Ok(v)) => v,
+        _ => usage(),
+    }
+}
+
+pub fn run(args: &[String]) {
+    let mut req = Requirements::default();
+    let mut out = None;
+    let count: usize = match args.first().map(|a| a.parse()) {
+        Some(Ok(count)) => count,
+        _ => usage(),
+    };
+
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--unique" => req.unique = true,
+            "--min-dead-ends" => req.min_dead_ends = parse_value(rest.next()),
+            "--min-salt" => req.min_forced_salt = parse_value(rest.next()),
+            "--max-positions" => req.max_positions = parse_value(rest.next()),
+            "--out" => out = Some(rest.next().cloned().unwrap_or_else(|| usage())),
+            _ => usage(),
+        }
+    }
+
+    let mut rng = thread_rng();
+    for n in 0..count {
+        let design = match design(&req, 1000, &mut rng) {
+            Some(design) => design,
+            None => {
+                println!("Gave up on deal {} after 1000 attempts.", n);
+                continue;
+            }
+        };
+        let d = design.difficulty;
+        let header = format!("# difficulty {:.2}: first move dead ends {:.2}, legal moves {:.1}, solutions {}, \
+            first trap {:?}\n# {:?}\n", d.rating, d.first_move_dead_ends, d.mean_legal_moves, d.solutions,
+            d.first_trap, d.stats);
+        print!("{}{}\n", header, design.board.to_text());
+
+        if let Some(ref dir) = out {
+            let path = Path::new(dir).join(format!("deal-{:03}.txt", n));
+            let mut file = File::create(&path).expect("Couldn't create deal file.");
+            write!(file, "{}{}", header, design.board.to_text()).expect("Couldn't write deal file.");
+        }
+    }
+}
+
+/// Prints the difficulty of each board file, easiest first.
+pub fn rate(paths: &[String]) {
+    let mut rated: Vec<(&String, Difficulty)> = paths.iter().filter_map(|path| {
+        let mut text = String::new();
+        let read = File::open(path).and_then(|mut f| f.read_to_string(&mut text));
+        if let Err(e) = read {
+            println!("{}: {}", path, e);
+            return None;
+        }
+        match Board::from_text(&text) {
+            Ok(board) => Some((path, board.difficulty())),
+            Err(e) => {
+                println!("{}: {}", path, e);
+                None
+            }
+        }
+    }).collect();
+    rated.sort_by(|a, b| a.1.rating.partial_cmp(&b.1.rating).unwrap());
+
+    for (path, d) in rated {
+        println!("{:6.2} {} (first move dead ends {:.2}, legal moves {:.1}, solutions {}, first trap {:?}{})",
+            d.rating, path, d.first_move_dead_ends, d.mean_legal_moves, d.solutions, d.first_trap,
+            if d.stats.complete { "" } else { ", partial" });
+    }
+}
@@ -14,38 +14,246 @@ extern crate dxgcap;
 mod mouse;
 mod sigmar;
 mod ocr;
+mod analysis;
+mod designer;
+mod tablebase;
+mod solver;
+mod depgraph;
+mod planner;
+mod correction;
+mod synthetic;
+mod simulator;
 
+use mouse::Mouse;
+use ocr::{OcrError, Screen};
 use sigmar::{Move, Marble};
 
+const TABLEBASE_PATH: &'static str = "endgames.sgtb";
+const COLOUR_MODEL_PATH: &'static str = "colours.txt";
+const PROFILE_DIR: &'static str = "profiles";
+/// Wait after the first of a run of failed reads, in ms. It doubles with each further one up to the max.
+const RETRY_WAIT: u64 = 500;
+const MAX_RETRY_WAIT: u64 = 8000;
+/// Failed reads in a row after which the bot gives up.
+const MAX_FAILED_READS: u32 = 10;
+
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(|a| a.as_str()) {
+        Some("design") => designer::run(&args[2..]),
+        Some("rate") => designer::rate(&args[2..]),
+        Some("tablebase") => tablebase::run(&args[2..]),
+        Some("compare") => solver::compare(&args[2..]),
+        Some("ocr-eval") => ocr::evaluate(&args[2..]),
+        Some("ocr-test") => ocr::regression(&args[2..], &configure_reader),
+        Some("render") => synthetic::run(&args[2..]),
+        Some("simulate") => simulate(&args[2..]),
+        Some("train-colours") => ocr::train_colours(&args[2..]),
+        Some("train-font") => ocr::train_font(&args[2..]),
+        _ => play(&args[1..]),
+    }
+}
+
+/// A reader with the profile named `profile_name` or else the one that reads the screen best, the glyphs
+/// from `font_dir` if given and the colour model if there is one. `None` after saying why if there's no
+/// such profile, none reads the screen or the font is bad.
+fn configure_reader<S: ocr::Screen>(profile_name: Option<&String>, font_dir: Option<&String>, screen: &mut S)
+                                    -> Option<ocr::BoardReader> {
+    let profiles = ocr::load_profiles(PROFILE_DIR);
+    let profile = match profile_name {
+        Some(name) => match profiles.into_iter().find(|p| &p.name == name) {
+            Some(profile) => profile,
+            None => {
+                println!("No profile named {} in {}", name, PROFILE_DIR);
+                return None;
+            },
+        },
+        None => match ocr::choose_profile(profiles, screen) {
+            Ok((profile, score)) => {
+                println!("Using profile {}, score {:.2}", profile.name, score);
+                profile
+            },
+            Err(e) => {
+                println!("{}", e);
+                return None;
+            },
+        },
+    };
+    let mut reader = ocr::BoardReader::with_profile(profile);
+    if let Some(dir) = font_dir {
+        if let Err(e) = reader.use_font_dir(dir) {
+            println!("{}", e);
+            return None;
+        }
+    }
+    match ocr::ColourModel::load(COLOUR_MODEL_PATH) {
+        Ok(model) => reader.colours = Some(model),
+        Err(e) => println!("Not using colours, {}: {}", COLOUR_MODEL_PATH, e),
+    }
+    Some(reader)
+}
+
+/// Plays games, with the profile named by `--profile <name>` or else the one that reads the screen
+/// best, and the glyphs from `--font <dir>` if given. With `--debug <dir>`, reads that weren't used and
+/// games that weren't solved are saved there as annotated screenshots.
+fn play(args: &[String]) {
+    let (mut font_dir, mut profile_name, mut debug_dir) = (None, None, None);
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--font" => font_dir = rest.next(),
+            "--profile" => profile_name = rest.next(),
+            "--debug" => debug_dir = rest.next(),
+            _ => println!("Ignoring {}", arg),
+        }
+    }
+    if let Some(dir) = debug_dir {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            println!("{}: {}", dir, e);
+            return;
+        }
+    }
+
+    let tablebase = match tablebase::Tablebase::load(TABLEBASE_PATH) {
+        Ok(tb) => Some(tb),
+        Err(e) => {
+            println!("Not using a tablebase, {}: {}", TABLEBASE_PATH, e);
+            None
+        },
+    };
 
-    for i in 0..100 {
-        let board = match ocr::ocr_game_board() {
-            None => panic!("Couldn't ocr. Board not visible?"),
-            Some(board) => board
+    let mut reader = match configure_reader(profile_name, font_dir, &mut ocr::Desktop) {
+        Some(reader) => reader,
+        None => return,
+    };
+    let save_debug = |reader: &ocr::BoardReader, read: &ocr::BoardRead, name: String| {
+        if let Some(dir) = debug_dir {
+            let path = std::path::Path::new(dir).join(name).display().to_string();
+            match reader.save_debug_image(read, &path) {
+                Ok(()) => println!("Saved {}", path),
+                Err(e) => println!("{}", e),
+            }
+        }
+    };
+    play_games(&mut reader, tablebase.as_ref(), &mut ocr::Desktop, &mut mouse::SystemMouse, 100, &save_debug);
+}
+
+/// Plays against `simulator::Simulator` instead of the game: `simulate <games> [--drop P] [--lag MS]
+/// [--seed N]`, with P the chance of a click being lost.
+fn simulate(args: &[String]) {
+    let usage = || -> ! {
+        println!("usage: sigmar_bot simulate <games> [--drop P] [--lag MS] [--seed N]");
+        std::process::exit(1)
+    };
+    let games: usize = args.first().and_then(|a| a.parse().ok()).unwrap_or_else(|| usage());
+    let (mut faults, mut seed) = (simulator::Faults::default(), 0);
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        let value = rest.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--drop" => faults.dropped_clicks = value.parse().unwrap_or_else(|_| usage()),
+            "--lag" => faults.lag = value.parse().unwrap_or_else(|_| usage()),
+            "--seed" => seed = value.parse().unwrap_or_else(|_| usage()),
+            _ => usage(),
+        }
+    }
+
+    let simulator = simulator::Simulator::new(synthetic::Scene::new(), faults, seed);
+    let mut reader = ocr::BoardReader::new();
+    let solved = play_games(&mut reader, None, &mut simulator.screen(), &mut simulator.mouse(), games, &|_, _, _| ());
+    println!("Solved {} of {} games, cleared {} of {} deals.", solved, games, simulator.wins(), simulator.deals());
+}
+
+/// Waits after the `failed`th read in a row that didn't work. False if it's time to give up instead.
+fn back_off<M: Mouse>(mouse: &mut M, error: &OcrError, failed: u32) -> bool {
+    if failed >= MAX_FAILED_READS {
+        println!("{}, giving up after {} tries.", error, failed);
+        return false;
+    }
+    let wait = std::cmp::min(RETRY_WAIT << (failed - 1), MAX_RETRY_WAIT);
+    println!("{}, reading again in {}ms.", error, wait);
+    mouse.wait(wait);
+    true
+}
+
+/// Reads, solves and clears `games` boards in a row, starting a new game after each. Returns how many
+/// were solved. Reads that fail are retried after a wait that grows while they keep failing, without
+/// counting towards the games.
+fn play_games<S: Screen, M: Mouse>(reader: &mut ocr::BoardReader, tablebase: Option<&tablebase::Tablebase>,
+                                   screen: &mut S, mouse: &mut M, games: usize,
+                                   save_debug: &Fn(&ocr::BoardReader, &ocr::BoardRead, String)) -> usize {
+    let (mut solved, mut failed) = (0, 0);
+    'games: for i in 0..games {
+        let (read, board) = loop {
+            let read = match reader.read(screen) {
+                Ok(read) => read,
+                Err(e) => {
+                    failed += 1;
+                    if back_off(mouse, &e, failed) { continue } else { break 'games }
+                },
+            };
+            if !read.free_disagreements.is_empty() {
+                println!("Brightness and the rules disagree on which marbles are free at {:?}.", read.free_disagreements);
+            }
+            // Cells too shaky to play on have to be either corrected or backed up by the counts. An empty
+            // board is between games rather than one to play. Only a relabelling has to prove itself by
+            // solving within the budget: a read that adds up as it is goes to the solver however hard.
+            let require_solvable = !correction::consistent(&read.board);
+            let correction = if read.board.marble_count() == 0 { None } else { correction::correct(&read, require_solvable) };
+            let error = match correction {
+                Some(ref correction) if correction.unconfirmed.is_empty() => {
+                    if !correction.changed.is_empty() {
+                        println!("Corrected the read at a cost of {:.2}.", correction.cost);
+                    }
+                    for &(pos, from, to) in &correction.changed {
+                        println!("Read {:?} as {:?} but counts say {:?}.", pos, from, to);
+                    }
+                    break (read, correction.board.clone());
+                },
+                Some(_) => OcrError::LowConfidence(read.confidence),
+                None => OcrError::InconsistentCounts,
+            };
+            let unsure = correction.as_ref().map_or(&read.low_confidence, |c| &c.unconfirmed);
+            for &(pos, ref r) in read.cells.iter().filter(|&&(pos, _)| unsure.contains(&pos)) {
+                println!("  {:?}: {:?}, {:?}, ahead by {}", pos, r.occupancy, &r.scores[..r.scores.len().min(2)], r.margin);
+            }
+            save_debug(reader, &read, format!("game-{}-unsure.png", i));
+            failed += 1;
+            if !back_off(mouse, &error, failed) { break 'games }
         };
-        let (new_x, new_y) = board.new_game_pos();
-        mouse::move_cursor(new_x, new_y);
+        failed = 0;
+        let (new_x, new_y) = reader.profile().new_game_pos(&board);
+        mouse.move_cursor(new_x, new_y);
 
-        if let Some(moves) = board.solve() {
-            println!("Counting to 100: {}", i);
-            for mov in moves {
+        let solution = match tablebase {
+            Some(tb) => board.solve_with(tb),
+            None => board.solve(),
+        };
+        if let Some(moves) = solution {
+            solved += 1;
+            println!("Counting to {}: {}", games, i);
+            let plan = planner::plan_clicks(&board, &moves, (new_x, new_y));
+            println!("Click order saves {:.1} tiles of cursor travel, about {:.2}s at hand speed.",
+                     plan.original_travel - plan.travel, plan.time_saved());
+            for mov in plan.moves {
                 let Move{a, b} = mov;
 
                 let (x1, y1) = board.pos_to_screen(a.x, a.y);
                 let (x2, y2) = board.pos_to_screen(b.x, b.y);
 
-                mouse::click_at(x1, y1);
+                mouse.click_at(x1, y1);
                 if b.marble != Marble::Gold {
-                    mouse::click_at(x2, y2);
+                    mouse.click_at(x2, y2);
                 }
             }
         }
         else {
             println!("Failed to solve. We live in terrible times, good friend.");
+            save_debug(reader, &read, format!("game-{}-unsolved.png", i));
         }
 
-        mouse::click_at(new_x, new_y);
-        std::thread::sleep(std::time::Duration::from_millis(4500));
+        mouse.click_at(new_x, new_y);
+        mouse.wait(4500);
     }
+    solved
 }
\ No newline at end of file
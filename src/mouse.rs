@@ -56,6 +56,12 @@ fn click(flags: u32) {
     });
 }
 
+/// Tile widths a cursor moved by hand crosses in a second, about 1300 pixels at 1080p, for estimating
+/// what a shorter click order saves. `click_at` jumps the cursor with an absolute move and then takes
+/// the same 130ms for every click, so for the bot itself travel is free: the estimate is of the time
+/// the order saves a cursor that has to glide there.
+pub const CURSOR_SPEED: f32 = 20.0;
+
 pub fn click_at(x: f32, y: f32) {
     move_cursor(x, y);
     std::thread::sleep( Duration::from_millis(10));
@@ -63,4 +69,29 @@ pub fn click_at(x: f32, y: f32) {
     std::thread::sleep( Duration::from_millis(60));
     click(LEFTUP);
     std::thread::sleep( Duration::from_millis(60));
-}
\ No newline at end of file
+}
+
+/// Where the bot's clicks go. Positions are fractions of the screen, as from `Board::pos_to_screen`.
+pub trait Mouse {
+    fn move_cursor(&mut self, x: f32, y: f32);
+    fn click_at(&mut self, x: f32, y: f32);
+    /// Gives the game time to catch up.
+    fn wait(&mut self, ms: u64);
+}
+
+/// The system mouse, through `SendInput`.
+pub struct SystemMouse;
+
+impl Mouse for SystemMouse {
+    fn move_cursor(&mut self, x: f32, y: f32) {
+        move_cursor(x, y)
+    }
+
+    fn click_at(&mut self, x: f32, y: f32) {
+        click_at(x, y)
+    }
+
+    fn wait(&mut self, ms: u64) {
+        std::thread::sleep(Duration::from_millis(ms));
+    }
+}
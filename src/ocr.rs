@@ -1,6 +1,6 @@
 use std;
 use image;
-use image::{ImageBuffer, Rgba};
+use image::{ImageBuffer, Luma, Rgb, Rgba};
 use scrap::{Capturer, Display};
 use std::io::ErrorKind::WouldBlock;
 use std::path::Path;
@@ -8,15 +8,20 @@ use std::thread;
 use std::time::Duration;
 use num;
 use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::time::Instant;
 use std::error::Error;
+use std::fmt;
 
-use sigmar::{board_rows, Board, Marble, MARBLE_VALUES};
+use sigmar::{board_cells, board_rows, Board, Marble, MARBLE_VALUES, NEW_GAME_OFFSET};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-struct SRGB {
-    r: u8,
-    g: u8,
-    b: u8,
+pub struct SRGB {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
 }
 
 impl SRGB {
@@ -28,7 +33,7 @@ impl SRGB {
     }
 }
 
-struct SRGBIntoIter {
+pub struct SRGBIntoIter {
     srgb: SRGB,
     pos: u8,
 }
@@ -60,7 +65,7 @@ macro_rules! pixel_arr {
         [ $( SRGB{r: $r, g: $g, b: $b},)* ]
     };
 }
-const GOLD_PIXEL_VALUES: [[SRGB;5];3] = [
+pub const GOLD_PIXEL_VALUES: [[SRGB;5];3] = [
     pixel_arr![(166, 147, 112), (189, 174, 139), (196, 181, 147), (194, 181, 147), (184, 168, 135)],
     pixel_arr![(161, 142, 109), (179, 161, 126), (194, 178, 142), (194, 177, 142), (175, 156, 123)],
     pixel_arr![(166, 147, 112), (163, 143, 110), (170, 150, 116), (168, 149, 115), (161, 141, 108)],
@@ -138,13 +143,13 @@ fn to_grayscale(pixel: SRGB) -> Grayscale {
 }
 
 #[derive(Clone, Debug)]
-struct Image<T> {
-    w: usize,
-    h: usize,
-    data: Vec<T>,
+pub struct Image<T> {
+    pub w: usize,
+    pub h: usize,
+    pub data: Vec<T>,
 }
 
-struct ImageIterMut<'a, T: 'a> {
+pub struct ImageIterMut<'a, T: 'a> {
     iter: std::slice::IterMut<'a, T>,
 }
 
@@ -354,68 +359,668 @@ fn canny(image: &Image<EdgeGradient>) -> Image<bool> {
     }
 }
 
-const TILE_WIDTH: f32 = 66.0;
-const TILE_HEIGHT: f32 = 57.0;
+/// Tile pitch of `Profile::opus_magnum`, at 1080p with the default UI scale. Other resolutions and UI
+/// scales are handled by `detect_scale`.
+pub const TILE_WIDTH: f32 = 66.0;
+pub const TILE_HEIGHT: f32 = 57.0;
+const MIN_SCALE: f32 = 0.5;
+const MAX_SCALE: f32 = 2.5;
+
+/// Size of the glyphs in a font, that of a marble's symbol at 1080p.
+const GLYPH_WIDTH: usize = 30;
+const GLYPH_HEIGHT: usize = 40;
+
+/// The glyphs in `symbol-font`, compiled in so the bot runs from any directory. In `MARBLE_VALUES` order.
+static EMBEDDED_FONT: [(Marble, &'static [u8]); 14] = [
+    (Marble::Salt, include_bytes!("../symbol-font/Salt.png")),
+    (Marble::Air, include_bytes!("../symbol-font/Air.png")),
+    (Marble::Fire, include_bytes!("../symbol-font/Fire.png")),
+    (Marble::Water, include_bytes!("../symbol-font/Water.png")),
+    (Marble::Earth, include_bytes!("../symbol-font/Earth.png")),
+    (Marble::Lead, include_bytes!("../symbol-font/Lead.png")),
+    (Marble::Tin, include_bytes!("../symbol-font/Tin.png")),
+    (Marble::Iron, include_bytes!("../symbol-font/Iron.png")),
+    (Marble::Copper, include_bytes!("../symbol-font/Copper.png")),
+    (Marble::Silver, include_bytes!("../symbol-font/Silver.png")),
+    (Marble::Gold, include_bytes!("../symbol-font/Gold.png")),
+    (Marble::Mercury, include_bytes!("../symbol-font/Mercury.png")),
+    (Marble::Vitae, include_bytes!("../symbol-font/Vitae.png")),
+    (Marble::Mors, include_bytes!("../symbol-font/Mors.png")),
+];
 
-fn get_font() -> Vec<(Marble, Image<bool>)> {
-    let path = |marble: Marble| -> String { format!("symbol-font/{}.png", marble.to_string()) };
-    (&MARBLE_VALUES[..]).iter().map(|&marble| {
-        let im = image::open(path(marble));
-        let image = match im {
-            Ok(image) => image,
-            Err(e) => panic!("Couldn't find sample image for {}, {}", marble.to_string(), e.description())
-        };
-        let luma = image.to_luma();
-        let pixels = luma.into_raw().iter().map(|&l| l > 100).collect();
-        (marble, Image{w: 30, h: 40, data: pixels})
+/// A glyph from its PNG. Pixels brighter than 100 are edges.
+fn decode_glyph(name: &str, image: image::ImageResult<image::DynamicImage>) -> Result<Image<bool>, String> {
+    let luma = image.map_err(|e| format!("Couldn't load {}, {}", name, e.description()))?.to_luma();
+    let (w, h) = luma.dimensions();
+    if (w as usize, h as usize) != (GLYPH_WIDTH, GLYPH_HEIGHT) {
+        return Err(format!("{} is {}x{}, glyphs have to be {}x{}", name, w, h, GLYPH_WIDTH, GLYPH_HEIGHT));
+    }
+    let pixels = luma.into_raw().iter().map(|&l| l > 100).collect();
+    Ok(Image{w: GLYPH_WIDTH, h: GLYPH_HEIGHT, data: pixels})
+}
+
+fn embedded_font() -> Result<Vec<(Marble, Image<bool>)>, String> {
+    EMBEDDED_FONT.iter().map(|&(marble, bytes)| {
+        let name = format!("embedded {}.png", marble.to_string());
+        Ok((marble, decode_glyph(&name, image::load_from_memory(bytes))?))
+    }).collect()
+}
+
+/// The glyphs in a directory, a `<Marble>.png` for each marble as `train-font` writes them.
+fn load_font_dir(dir: &str) -> Result<Vec<(Marble, Image<bool>)>, String> {
+    MARBLE_VALUES.iter().map(|&marble| {
+        let path = Path::new(dir).join(format!("{}.png", marble.to_string()));
+        Ok((marble, decode_glyph(&path.display().to_string(), image::open(&path))?))
     }).collect()
 }
 
 lazy_static! {
-    static ref FONT: Vec<(Marble, Image<bool>)> = {
-        get_font()
+    pub static ref FONT: Vec<(Marble, Image<bool>)> = {
+        embedded_font().unwrap_or_else(|e| panic!("Embedded font is broken: {}", e))
     };
 }
 
+/// How one release of the game at one resolution draws the board.
+#[derive(Clone)]
+pub struct Profile {
+    pub name: String,
+    /// The middle 5x3 pixels of the gold marble's symbol, like `GOLD_PIXEL_VALUES`.
+    gold: [[SRGB; 5]; 3],
+    /// Tile pitch at scale 1. Other sizes are handled by `detect_scale`.
+    tile: (f32, f32),
+    /// The new game button, in tiles right and down from the board centre.
+    new_game: (f32, f32),
+    /// The font at scale 1.
+    glyphs: Vec<(Marble, Image<bool>)>,
+}
+
+impl Profile {
+    /// Opus Magnum at 1080p, what the built-in constants and font are for.
+    pub fn opus_magnum() -> Profile {
+        Profile {
+            name: "opus-magnum".to_string(), gold: GOLD_PIXEL_VALUES, tile: (TILE_WIDTH, TILE_HEIGHT),
+            new_game: NEW_GAME_OFFSET, glyphs: FONT.clone(),
+        }
+    }
+
+    /// A profile file has a key and its values on each line, `#` starting a comment:
+    ///
+    /// * `name <name>`
+    /// * `tile <width> <height>`, the tile pitch in pixels
+    /// * `gold <r,g,b> ...`, the 15 pixels of `GOLD_PIXEL_VALUES` row by row
+    /// * `new-game <x> <y>`, optional, tiles right and down from the centre
+    /// * `font <dir>`, optional, glyphs as `load_font_dir` reads them, relative to the file
+    pub fn load(path: &str) -> Result<Profile, String> {
+        let mut text = String::new();
+        File::open(path).and_then(|mut f| f.read_to_string(&mut text)).map_err(|e| format!("{}: {}", path, e))?;
+        let dir = Path::new(path).parent().unwrap_or(Path::new("."));
+        Profile::parse(&text, dir).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    fn parse(text: &str, dir: &Path) -> Result<Profile, String> {
+        let (mut name, mut gold, mut tile, mut new_game, mut glyphs) = (None, None, None, NEW_GAME_OFFSET, None);
+        for (n, line) in text.lines().enumerate() {
+            let mut words = line.split('#').next().unwrap().split_whitespace();
+            let key = match words.next() {
+                Some(key) => key,
+                None => continue,
+            };
+            let values: Vec<&str> = words.collect();
+            let error = |what: &str| format!("line {}: {} {}", n + 1, key, what);
+            let pair = || -> Result<(f32, f32), String> {
+                let numbers: Vec<f32> = values.iter().map(|v| v.parse()).collect::<Result<_, _>>()
+                    .map_err(|_| error("takes numbers"))?;
+                if numbers.len() != 2 { return Err(error("takes two numbers")) }
+                Ok((numbers[0], numbers[1]))
+            };
+            match key {
+                "name" => name = Some(values.join(" ")),
+                "tile" => tile = Some(pair()?),
+                "new-game" => new_game = pair()?,
+                "gold" => {
+                    if values.len() != 15 { return Err(error("takes 15 pixels")) }
+                    let mut pixels = GOLD_PIXEL_VALUES;
+                    for (i, v) in values.iter().enumerate() {
+                        let c: Vec<u8> = v.split(',').map(|c| c.parse()).collect::<Result<_, _>>()
+                            .map_err(|_| error(&format!("has a bad pixel {}", v)))?;
+                        if c.len() != 3 { return Err(error(&format!("has a bad pixel {}", v))) }
+                        pixels[i / 5][i % 5] = SRGB{r: c[0], g: c[1], b: c[2]};
+                    }
+                    gold = Some(pixels);
+                },
+                "font" => glyphs = Some(load_font_dir(&dir.join(values.join(" ")).display().to_string())?),
+                _ => return Err(format!("line {}: unknown key {}", n + 1, key)),
+            }
+        }
+        Ok(Profile {
+            name: name.ok_or("no name".to_string())?,
+            gold: gold.ok_or("no gold".to_string())?,
+            tile: tile.ok_or("no tile".to_string())?,
+            new_game,
+            glyphs: glyphs.unwrap_or_else(|| FONT.clone()),
+        })
+    }
+
+    /// Where to click for a new game, as a fraction of the screen like `Board::pos_to_screen`.
+    pub fn new_game_pos(&self, board: &Board) -> (f32, f32) {
+        board.tiles_from_centre(self.new_game)
+    }
+}
+
+/// The built-in profile followed by one for each file in `dir`. Files that don't load are reported and
+/// left out.
+pub fn load_profiles(dir: &str) -> Vec<Profile> {
+    let mut profiles = vec![Profile::opus_magnum()];
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return profiles,
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        match Profile::load(&entry.path().display().to_string()) {
+            Ok(profile) => profiles.push(profile),
+            Err(e) => println!("Skipping profile {}", e),
+        }
+    }
+    profiles
+}
+
+/// Reads the image with each profile and returns the index of the one that finds the board most
+/// clearly and matches its glyphs best, with its score: the localisation quality plus the mean best
+/// glyph score of the occupied cells, up to 2. If none can read it, the first profile's error.
+fn detect_profile(profiles: &[Profile], image: &Image<SRGB>) -> Result<(usize, f32), OcrError> {
+    let mut first_error = None;
+    let best = profiles.iter().enumerate().filter_map(|(i, profile)| {
+        let read = match BoardReader::with_profile(profile.clone()).read_image(image) {
+            Ok(read) => read,
+            Err(e) => {
+                first_error = first_error.take().or(Some(e));
+                return None;
+            },
+        };
+        let matches: Vec<f32> = read.cells.iter()
+            .filter(|&&(_, ref r)| r.occupancy.occupied)
+            .map(|&(_, ref r)| r.edge_scores.first().map_or(0.0, |s| s.1))
+            .collect();
+        let recognition = matches.iter().sum::<f32>() / std::cmp::max(matches.len(), 1) as f32;
+        Some((i, read.localisation + recognition))
+    }).max_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+    best.ok_or_else(|| first_error.unwrap_or(OcrError::BoardNotFound { best_distance: std::f32::MAX }))
+}
+
+/// The profile that reads the screen best, see `detect_profile`.
+pub fn choose_profile<S: Screen>(mut profiles: Vec<Profile>, screen: &mut S) -> Result<(Profile, f32), OcrError> {
+    let (i, score) = detect_profile(&profiles, &screen.capture(None)?.to_image())?;
+    Ok((profiles.swap_remove(i), score))
+}
+
+/// The font with each glyph resized by `scale`.
+pub fn scaled_font(font: &[(Marble, Image<bool>)], scale: f32) -> Vec<(Marble, Image<bool>)> {
+    if (scale - 1.0).abs() < 0.01 { return font.to_vec() }
+    font.iter().map(|&(marble, ref glyph)| {
+        (marble, resize_edges(glyph, (glyph.w as f32 * scale).round() as usize, (glyph.h as f32 * scale).round() as usize))
+    }).collect()
+}
+
+/// Edge image resized to w×h. A pixel is an edge if any pixel it covers in the original is, so thin
+/// edges survive shrinking.
+fn resize_edges(edges: &Image<bool>, w: usize, h: usize) -> Image<bool> {
+    let span = |d: usize, len: usize, size: usize| {
+        let scale = size as f32 / len as f32;
+        let start = (d as f32 / scale) as usize;
+        let end = std::cmp::max(start + 1, ((d + 1) as f32 / scale).ceil() as usize);
+        start..std::cmp::min(end, len)
+    };
+    let mut data = Vec::with_capacity(w * h);
+    for y in 0..h {
+        for x in 0..w {
+            data.push(span(y, edges.h, h).any(|sy| span(x, edges.w, w).any(|sx| edges[sy * edges.w + sx])));
+        }
+    }
+    Image{w, h, data}
+}
+
+/// Size of the board relative to a layout with tiles `tile_w` wide, from the spacing of marbles on the
+/// row through the board centre: the autocorrelation of the horizontal edge profile peaks at the tile
+/// pitch.
+fn detect_scale(image: &Image<SRGB>, centre_x: i32, centre_y: i32, tile_w: f32) -> f32 {
+    let gray = |x: i32, y: i32| -> f32 {
+        let (x, y) = (num::clamp(x, 0, image.w as i32 - 1), num::clamp(y, 0, image.h as i32 - 1));
+        let Grayscale(g) = to_grayscale(image[y as usize * image.w + x as usize]);
+        g
+    };
+    let reach = (tile_w * MAX_SCALE * 5.5) as i32;
+    let profile: Vec<f32> = (-reach..reach).map(|dx| {
+        let x = centre_x + dx;
+        (-8..9).map(|dy| (gray(x + 1, centre_y + dy) - gray(x, centre_y + dy)).abs()).sum()
+    }).collect();
+    let profile: Vec<f32> = (0..profile.len()).map(|i| {
+        let at = |j: usize| profile[num::clamp(j, 1, profile.len()) - 1];
+        at(i) + 2.0 * at(i + 1) + at(i + 2)
+    }).collect();
+
+    let (min_pitch, max_pitch) = ((tile_w * MIN_SCALE) as usize, (tile_w * MAX_SCALE) as usize);
+    best_period(|lag| autocorrelation(&profile, lag), min_pitch, max_pitch) / tile_w
+}
+
+/// Normalised correlation of the profile with itself shifted by `lag`.
+fn autocorrelation(profile: &[f32], lag: usize) -> f32 {
+    if lag >= profile.len() { return 0.0 }
+    let (mut ab, mut aa, mut bb) = (0.0, 0.0, 0.0);
+    for i in 0..profile.len() - lag {
+        let (a, b) = (profile[i], profile[i + lag]);
+        ab += a * b;
+        aa += a * a;
+        bb += b * b;
+    }
+    if aa * bb > 0.0 { ab / (aa * bb).sqrt() } else { 0.0 }
+}
+
+/// The lag in `min..max` with the highest autocorrelation `score`, to a fraction of a pixel. A lag
+/// scores by its own correlation and that of twice it, since patterns inside a marble can repeat at
+/// a shorter lag but not again at the double.
+fn best_period<F: Fn(usize) -> f32>(score: F, min: usize, max: usize) -> f32 {
+    let raw: Vec<f32> = (0..2 * max + 4).map(|p| if p >= min { score(p) } else { 0.0 }).collect();
+    let scores: Vec<f32> = (0..max + 2).map(|p| (raw[p] + raw[2 * p]) / 2.0).collect();
+    let mut best = (min..max + 1).max_by(|&a, &b| scores[a].partial_cmp(&scores[b]).unwrap()).unwrap();
+    // Every second or third marble lines up too, so a peak at a fraction of the period that's nearly as
+    // high is the real one.
+    if let Some(k) = (2..5).rev().find(|&k| best / k >= min && scores[best / k] > 0.7 * scores[best]) {
+        best /= k;
+    }
+
+    // Sub-pixel peak from a parabola through the neighbouring scores.
+    let (l, c, r) = (scores[best - 1], scores[best], scores[best + 1]);
+    let denom = l - 2.0 * c + r;
+    let offset = if denom < 0.0 { num::clamp(0.5 * (l - r) / denom, -0.5, 0.5) } else { 0.0 };
+    best as f32 + offset
+}
+
 fn matching_pixels(a: &Image<bool>, b: &Image<bool>) -> i32 {
     a.data.iter().enumerate().map(|(i, &k)| if k && b[i] { 1i32 } else { 0i32 }).sum()
 }
 
-fn recognize_marble_at(desktop_image: &Image<SRGB>, x: i32, y: i32) -> Marble {
-    let mut data: Vec<SRGB> = Vec::with_capacity(30*40);
-    for my in -20..20 {
-        for mx in -15..15 {
-            data.push(desktop_image[(x + mx) as usize + (y+my) as usize * desktop_image.w]);
+/// How a patch's edges are compared with a glyph's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// Edge pixels set in both, with the glyph exactly where the patch was cut. Favours glyphs with
+    /// many edges.
+    Overlap,
+    /// F-score of the edge pixels that have an edge of the other image within a pixel, the best over
+    /// small shifts and rescalings of the glyph.
+    Chamfer,
+}
+
+pub const METRICS: [Metric; 2] = [Metric::Overlap, Metric::Chamfer];
+
+/// Shifts of the glyph tried by `Metric::Chamfer`, in pixels either way.
+const CHAMFER_SHIFT: i32 = 2;
+/// Rescalings of the glyph tried by `Metric::Chamfer`.
+const CHAMFER_SCALES: [f32; 3] = [0.95, 1.0, 1.05];
+
+/// Chessboard distance from each pixel to the nearest edge, capped at 255.
+fn distance_transform(edges: &Image<bool>) -> Image<u8> {
+    let (w, h) = (edges.w, edges.h);
+    let mut d: Vec<u8> = edges.data.iter().map(|&e| if e { 0 } else { 255 }).collect();
+    let relax = |d: &mut Vec<u8>, i: usize, j: usize| {
+        if d[j] < 255 && d[j] + 1 < d[i] { d[i] = d[j] + 1 }
+    };
+    for y in 0..h {
+        for x in 0..w {
+            let i = y * w + x;
+            if x > 0 { relax(&mut d, i, i - 1) }
+            if y > 0 {
+                relax(&mut d, i, i - w);
+                if x > 0 { relax(&mut d, i, i - w - 1) }
+                if x + 1 < w { relax(&mut d, i, i - w + 1) }
+            }
+        }
+    }
+    for y in (0..h).rev() {
+        for x in (0..w).rev() {
+            let i = y * w + x;
+            if x + 1 < w { relax(&mut d, i, i + 1) }
+            if y + 1 < h {
+                relax(&mut d, i, i + w);
+                if x + 1 < w { relax(&mut d, i, i + w + 1) }
+                if x > 0 { relax(&mut d, i, i + w - 1) }
+            }
+        }
+    }
+    Image{w, h, data: d}
+}
+
+/// A glyph prepared for `Metric::Chamfer`.
+struct Template {
+    marble: Marble,
+    edges: Vec<(i32, i32)>,
+    distance: Image<u8>,
+}
+
+impl Template {
+    fn new(marble: Marble, glyph: &Image<bool>) -> Template {
+        let edges = (0..glyph.data.len()).filter(|&i| glyph[i])
+            .map(|i| ((i % glyph.w) as i32, (i / glyph.w) as i32))
+            .collect();
+        Template { marble, edges, distance: distance_transform(glyph) }
+    }
+
+    /// F-score against a patch's edges at the best of the small shifts.
+    fn chamfer_score(&self, patch_edges: &[(i32, i32)], patch_distance: &Image<u8>) -> f32 {
+        if self.edges.is_empty() || patch_edges.is_empty() { return 0.0 }
+        let near = |d: &Image<u8>, x: i32, y: i32| {
+            x >= 0 && y >= 0 && x < d.w as i32 && y < d.h as i32 && d[y as usize * d.w + x as usize] <= 1
+        };
+        let (cx, cy) = ((patch_distance.w as i32 - self.distance.w as i32) / 2,
+                        (patch_distance.h as i32 - self.distance.h as i32) / 2);
+        let mut best = 0.0;
+        for dy in -CHAMFER_SHIFT..CHAMFER_SHIFT + 1 {
+            for dx in -CHAMFER_SHIFT..CHAMFER_SHIFT + 1 {
+                let (ox, oy) = (cx + dx, cy + dy);
+                let found = self.edges.iter().filter(|&&(x, y)| near(patch_distance, x + ox, y + oy)).count();
+                let explained = patch_edges.iter().filter(|&&(x, y)| near(&self.distance, x - ox, y - oy)).count();
+                let recall = found as f32 / self.edges.len() as f32;
+                let precision = explained as f32 / patch_edges.len() as f32;
+                if recall + precision > 0.0 {
+                    let f = 2.0 * recall * precision / (recall + precision);
+                    if f > best { best = f }
+                }
+            }
         }
+        best
     }
-    let gray_marble = Image{w: 30, h: 40, data: data.iter().map(|p| to_grayscale(*p)).collect()};
+}
 
-    let gauss = gray_marble.convolute(&GAUSS);
+/// The glyphs at the board's scale, and the templates `Metric::Chamfer` uses.
+struct Font {
+    glyphs: Vec<(Marble, Image<bool>)>,
+    templates: Vec<Template>,
+}
 
-    let sobel_image = sobel(&gauss);
-    let canny_image = canny(&sobel_image);
+impl Font {
+    fn new(glyphs: &[(Marble, Image<bool>)], scale: f32) -> Font {
+        let templates = CHAMFER_SCALES.iter()
+            .flat_map(|&s| scaled_font(glyphs, scale * s))
+            .map(|(marble, glyph)| Template::new(marble, &glyph))
+            .collect();
+        Font { glyphs: scaled_font(glyphs, scale), templates }
+    }
+}
+
+/// Whether a cell holds a marble, decided from the patch before any glyph matching.
+#[derive(Debug, Clone, Copy)]
+pub struct Occupancy {
+    pub occupied: bool,
+    /// How far the evidence is from the threshold: 0 right at it, 1 at twice or half of it.
+    pub confidence: f32,
+    /// Gradient and contrast of the patch relative to what a marble is expected to have, averaged.
+    /// Cells above `OCCUPIED_THRESHOLD` hold a marble.
+    pub evidence: f32,
+}
+
+/// Mean Sobel intensity and luminance deviation that a patch scores 1 on each, for `Occupancy`.
+const OCCUPIED_GRADIENT: f32 = 0.04;
+const OCCUPIED_DEVIATION: f32 = 0.05;
+pub const OCCUPIED_THRESHOLD: f32 = 1.0;
+
+impl Occupancy {
+    fn from_evidence(evidence: f32) -> Occupancy {
+        let confidence = if evidence > 0.0 {
+            (evidence / OCCUPIED_THRESHOLD).ln().abs() / 2f32.ln()
+        } else { 1.0 };
+        Occupancy { occupied: evidence > OCCUPIED_THRESHOLD, confidence: confidence.min(1.0), evidence }
+    }
+
+    /// Measures a blurred grayscale patch and its gradients. Empty tiles are flat, marbles have
+    /// strong outlines and symbols.
+    fn measure(gray: &Image<Grayscale>, gradients: &Image<EdgeGradient>) -> Occupancy {
+        let n = gray.data.len() as f32;
+        let mean = gray.data.iter().map(|&Grayscale(g)| g).sum::<f32>() / n;
+        let deviation = (gray.data.iter().map(|&Grayscale(g)| (g - mean) * (g - mean)).sum::<f32>() / n).sqrt();
+        let gradient = gradients.data.iter().map(|e| e.intensity).sum::<f32>() / n;
+        Occupancy::from_evidence(0.5 * (gradient / OCCUPIED_GRADIENT + deviation / OCCUPIED_DEVIATION))
+    }
+}
+
+/// What a cell was read as and how sure the read is.
+#[derive(Debug, Clone)]
+pub struct Recognition {
+    pub occupancy: Occupancy,
+    /// Every glyph with its score under the metric used, best first. Empty for unoccupied cells.
+    pub scores: Vec<(Marble, f32)>,
+    /// Best score over the runner-up.
+    pub margin: f32,
+    /// The margin relative to the best score: 0 for a tie, 1 when nothing else matched at all.
+    pub confidence: f32,
+    /// The glyph scores before colour was taken into account, scaled to 0..1, best first.
+    pub edge_scores: Vec<(Marble, f32)>,
+    /// Scores from the colour model, best first. Empty without a model.
+    pub colour_scores: Vec<(Marble, f32)>,
+    /// Hue and saturation histogram of the middle of the patch, see `colour_histogram`.
+    pub histogram: Vec<f32>,
+    /// Mean linear luminance of the middle of the patch.
+    pub brightness: f32,
+    /// Whether the marble is drawn bright, as the game does for marbles that can be selected. Set by
+    /// `BoardRead`, which compares marbles of a kind; `None` if there was nothing to compare with.
+    pub appears_free: Option<bool>,
+}
+
+impl Recognition {
+    pub fn from_scores(occupancy: Occupancy, mut scores: Vec<(Marble, f32)>) -> Recognition {
+        scores.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        let best = scores.first().map_or(0.0, |s| s.1);
+        let second = scores.get(1).map_or(0.0, |s| s.1);
+        let margin = best - second;
+        Recognition {
+            occupancy,
+            scores,
+            margin,
+            confidence: if best > 0.0 { margin / best } else { 1.0 },
+            edge_scores: Vec::new(),
+            colour_scores: Vec::new(),
+            histogram: Vec::new(),
+            brightness: 0.0,
+            appears_free: None,
+        }
+    }
+
+    /// The best match, `Empty` if the cell looks unoccupied.
+    pub fn marble(&self) -> Marble {
+        match self.scores.first() {
+            Some(&(marble, _)) if self.occupancy.occupied => marble,
+            _ => Marble::Empty,
+        }
+    }
+
+    /// The lower of the occupancy confidence and, for occupied cells, the symbol confidence.
+    pub fn certainty(&self) -> f32 {
+        if self.occupancy.occupied { self.occupancy.confidence.min(self.confidence) } else { self.occupancy.confidence }
+    }
+}
+
+/// HSV of a pixel, hue in 0..6.
+fn to_hsv(SRGB{r, g, b}: SRGB) -> (f32, f32, f32) {
+    let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let chroma = max - min;
+    let hue = if chroma == 0.0 { 0.0 }
+        else if max == r { ((g - b) / chroma + 6.0) % 6.0 }
+        else if max == g { (b - r) / chroma + 2.0 }
+        else { (r - g) / chroma + 4.0 };
+    (hue, if max > 0.0 { chroma / max } else { 0.0 }, max)
+}
+
+const HUE_BINS: usize = 12;
+const GREY_BINS: usize = 4;
+/// Pixels less saturated than this are binned by value instead of hue.
+const GREY_SATURATION: f32 = 0.2;
+
+/// Normalised histogram of hue, in two saturation bands, and of value for pixels that are nearly
+/// grey. Value only matters for the grey bins, so dimming a marble barely moves it.
+fn colour_histogram(pixels: &[SRGB]) -> Vec<f32> {
+    let mut histogram = vec![0.0; 2 * HUE_BINS + GREY_BINS];
+    for &p in pixels {
+        let (h, s, v) = to_hsv(p);
+        let bin = if s < GREY_SATURATION {
+            2 * HUE_BINS + std::cmp::min((v * GREY_BINS as f32) as usize, GREY_BINS - 1)
+        } else {
+            let band = if s < 0.5 { 0 } else { HUE_BINS };
+            band + std::cmp::min((h / 6.0 * HUE_BINS as f32) as usize, HUE_BINS - 1)
+        };
+        histogram[bin] += 1.0;
+    }
+    let n = pixels.len().max(1) as f32;
+    histogram.into_iter().map(|c| c / n).collect()
+}
+
+/// Mean colour histogram of each marble, learned from labelled screenshots by `train-colours`.
+pub struct ColourModel {
+    prototypes: Vec<(Marble, Vec<f32>)>,
+}
+
+/// Share of the colour scores in the fused scores.
+pub const COLOUR_WEIGHT: f32 = 0.3;
+
+impl ColourModel {
+    /// Averages the histograms of each marble. Marbles without samples get no prototype.
+    pub fn train(samples: &[(Marble, Vec<f32>)]) -> ColourModel {
+        let prototypes = MARBLE_VALUES.iter().filter_map(|&marble| {
+            let mine: Vec<&Vec<f32>> = samples.iter().filter(|s| s.0 == marble).map(|s| &s.1).collect();
+            if mine.is_empty() { return None }
+            let mut mean = vec![0.0; mine[0].len()];
+            for h in &mine {
+                for (m, v) in mean.iter_mut().zip(h.iter()) { *m += v / mine.len() as f32 }
+            }
+            Some((marble, mean))
+        }).collect();
+        ColourModel { prototypes }
+    }
+
+    /// Histogram intersection with each prototype, 1 for the same colours.
+    pub fn scores(&self, histogram: &[f32]) -> Vec<(Marble, f32)> {
+        self.prototypes.iter().map(|&(marble, ref p)| {
+            (marble, p.iter().zip(histogram.iter()).map(|(a, b)| a.min(*b)).sum())
+        }).collect()
+    }
+
+    /// One line per marble: its symbol and the histogram.
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut out = File::create(path)?;
+        for &(marble, ref p) in &self.prototypes {
+            let values: Vec<String> = p.iter().map(|v| v.to_string()).collect();
+            writeln!(out, "{} {}", marble.symbol(), values.join(" "))?;
+        }
+        Ok(())
+    }
+
+    pub fn load(path: &str) -> io::Result<ColourModel> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        let prototypes = text.lines().filter(|l| !l.trim().is_empty()).map(|line| {
+            let mut fields = line.split_whitespace();
+            let symbol = fields.next().and_then(|f| f.chars().next()).unwrap_or(' ');
+            let marble = Marble::from_symbol(symbol).ok_or_else(|| invalid(format!("unknown marble '{}'", symbol)))?;
+            let p = fields.map(|f| f.parse::<f32>().map_err(|e| invalid(e.to_string()))).collect::<io::Result<Vec<f32>>>()?;
+            if p.len() != 2 * HUE_BINS + GREY_BINS {
+                return Err(invalid(format!("{} bins for {:?}", p.len(), marble)));
+            }
+            Ok((marble, p))
+        }).collect::<io::Result<Vec<_>>>()?;
+        Ok(ColourModel { prototypes })
+    }
+}
 
-    let mut best_match = Marble::Empty;
-    let mut best_match_count = 0;
+/// Weighted sum of edge and colour scores, both 0..1. Marbles the colour model doesn't know get the
+/// mean colour score, so they neither win nor lose by it. Without colour scores, the edge scores.
+pub fn fuse(edge: &[(Marble, f32)], colour: &[(Marble, f32)], weight: f32) -> Vec<(Marble, f32)> {
+    if colour.is_empty() { return edge.to_vec() }
+    let neutral = colour.iter().map(|c| c.1).sum::<f32>() / colour.len() as f32;
+    edge.iter().map(|&(marble, e)| {
+        let c = colour.iter().find(|c| c.0 == marble).map_or(neutral, |c| c.1);
+        (marble, (1.0 - weight) * e + weight * c)
+    }).collect()
+}
 
-    for &(sample_marble, ref sample_image) in FONT.iter() {
-        let matching = matching_pixels(&canny_image, sample_image);
-        if matching > best_match_count {
-            best_match_count = matching;
-            best_match = sample_marble;
+/// The w×h pixels centred at (x, y), row by row. Pixels past the edge of the image repeat the edge.
+fn cut_patch(image: &Image<SRGB>, x: i32, y: i32, w: i32, h: i32) -> Vec<SRGB> {
+    let mut data: Vec<SRGB> = Vec::with_capacity((w * h) as usize);
+    for my in -h/2..h - h/2 {
+        for mx in -w/2..w - w/2 {
+            let (px, py) = (num::clamp(x + mx, 0, image.w as i32 - 1), num::clamp(y + my, 0, image.h as i32 - 1));
+            data.push(image[px as usize + py as usize * image.w]);
         }
     }
+    data
+}
+
+/// Cuts out a patch the size of the font's glyphs centred at (x, y) and matches it against them, and
+/// against the colour model if there is one.
+fn recognize_marble_at(desktop_image: &Image<SRGB>, x: i32, y: i32, font: &Font, metric: Metric,
+                       colours: Option<&ColourModel>) -> Recognition {
+    let (w, h) = (font.glyphs[0].1.w as i32, font.glyphs[0].1.h as i32);
+    let data = cut_patch(desktop_image, x, y, w, h);
+    let gray_marble = Image{w: w as usize, h: h as usize, data: data.iter().map(|p| to_grayscale(*p)).collect()};
+
+    let gauss = gray_marble.convolute(&GAUSS);
+
+    let sobel_image = sobel(&gauss);
+    let occupancy = Occupancy::measure(&gauss, &sobel_image);
+    if !occupancy.occupied {
+        return Recognition::from_scores(occupancy, Vec::new());
+    }
+    let canny_image = canny(&sobel_image);
 
-    best_match
+    let (w, h) = (w as usize, h as usize);
+    let middle: Vec<usize> = (h / 4..h - h / 4).flat_map(|y| (w / 4..w - w / 4).map(move |x| y * w + x)).collect();
+    let brightness = middle.iter().map(|&i| { let Grayscale(g) = gray_marble.data[i]; g }).sum::<f32>() / middle.len() as f32;
+    let histogram = colour_histogram(&middle.iter().map(|&i| data[i]).collect::<Vec<_>>());
+
+    let mut edge_scores = match metric {
+        Metric::Overlap => {
+            let counts: Vec<(Marble, f32)> = font.glyphs.iter()
+                .map(|&(sample_marble, ref sample_image)| (sample_marble, matching_pixels(&canny_image, sample_image) as f32))
+                .collect();
+            let most = counts.iter().map(|c| c.1).fold(1.0, f32::max);
+            counts.into_iter().map(|(m, c)| (m, c / most)).collect()
+        },
+        Metric::Chamfer => {
+            let edges: Vec<(i32, i32)> = (0..canny_image.data.len()).filter(|&i| canny_image[i])
+                .map(|i| ((i % canny_image.w) as i32, (i / canny_image.w) as i32))
+                .collect();
+            let distance = distance_transform(&canny_image);
+            let mut scores: Vec<(Marble, f32)> = font.glyphs.iter().map(|&(m, _)| (m, 0.0)).collect();
+            for t in &font.templates {
+                let score = t.chamfer_score(&edges, &distance);
+                let entry = scores.iter_mut().find(|s| s.0 == t.marble).unwrap();
+                if score > entry.1 { entry.1 = score }
+            }
+            scores
+        },
+    };
+    let mut colour_scores = colours.map_or(Vec::new(), |model| model.scores(&histogram));
+
+    let mut recognition = Recognition::from_scores(occupancy, fuse(&edge_scores, &colour_scores, COLOUR_WEIGHT));
+    edge_scores.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+    colour_scores.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+    recognition.edge_scores = edge_scores;
+    recognition.colour_scores = colour_scores;
+    recognition.histogram = histogram;
+    recognition.brightness = brightness;
+    recognition
 }
 
-fn capture(mut capturer: Capturer) -> Vec<u8> {
+/// Copies only the rows of the region out of the capturer's buffer.
+fn capture(mut capturer: Capturer, region: Option<Region>) -> Result<Frame, OcrError> {
+    let (screen_w, screen_h) = (capturer.width(), capturer.height());
     loop {
         match capturer.frame() {
             Ok(buffer) => {
-                let mut v = vec![0; buffer.len()];
-                v.copy_from_slice(&(*buffer));
-                return v;
+                // _save_screenshot(&buffer.to_vec(), screen_w, screen_h);
+
+                // Rows can be padded, which only shows in the length of the buffer.
+                let stride = if screen_h > 0 { buffer.len() / screen_h } else { screen_w * 4 };
+                return Frame::cut(&buffer, screen_w, screen_h, stride, PixelFormat::Bgra, region).map_err(OcrError::Capture);
             },
             Err(error) => {
                 if error.kind() == WouldBlock {
@@ -423,79 +1028,1208 @@ fn capture(mut capturer: Capturer) -> Vec<u8> {
                     thread::sleep( Duration::new(0, 500_000));
                     continue;
                 } else {
-                    panic!("Capture error: {}", error);
+                    return Err(OcrError::Capture(error));
                 }
             }
         };
     }
 }
 
-pub fn ocr_game_board() -> Option<Board>{
-    let display = Display::primary().expect("Couldn't find primary display.");
-    let capturer = Capturer::new(display).expect("Couldn't begin capture.");
-    let (screen_w, screen_h) = (capturer.width(), capturer.height());
-
-    let buffer = capture(capturer);
-
-    // _save_screenshot(&buffer, screen_w, screen_h);
-
-    let desktop_image: Image<SRGB> = Image{
-        w: screen_w, h: screen_h,
-        data: (&buffer[..]).chunks(4).map(|chunk: &[u8]| {
-                match chunk {
-                    &[b, g, r, _a] => SRGB{r, g, b},
-                    _ => unreachable!()
-                }
-            }).collect()
-    };
+/// Sum of distances between the gold template and the pixels around (x, y).
+fn gold_distance(image: &Image<SRGB>, gold: &[[SRGB; 5]; 3], x: usize, y: usize) -> f32 {
+    let mut d: f32 = 0.0;
+    for my in 0..3 {
+        for mx in 0..5 {
+            let index = (y + my - 1) * image.w + (x + mx - 2);
+            d += image[index].eucl_dist(&gold[my][mx]);
+        }
+    }
+    d
+}
 
+/// Best gold match with its centre in the given ranges, clipped to the search margin of the screen.
+fn search_gold(image: &Image<SRGB>, gold: &[[SRGB; 5]; 3], xs: (i32, i32), ys: (i32, i32)) -> (f32, (i32, i32)) {
     let mut best_dist = ::std::f32::MAX;
     let mut best_coord = (0,0);
+    if image.w < 20 || image.h < 20 { return (best_dist, best_coord) }
 
-    for y in 10..(screen_h - 10) {
-        for x in 10..(screen_w - 10) {
-            let mut d: f32 = 0.0;
-            for my in 0..3 {
-                for mx in 0..5 {
-                    let index = (y + my - 1) * screen_w + (x + mx - 2);
-                    d += desktop_image[index].eucl_dist(&GOLD_PIXEL_VALUES[my][mx]);
-                }
-            }
+    let clip = |(lo, hi): (i32, i32), len: usize| {
+        (std::cmp::max(lo, 10) as usize, num::clamp(hi, 10, len as i32 - 10) as usize)
+    };
+    let ((x_min, x_max), (y_min, y_max)) = (clip(xs, image.w), clip(ys, image.h));
+    for y in y_min..y_max {
+        for x in x_min..x_max {
+            let d = gold_distance(image, gold, x, y);
             if d < best_dist {
                 best_dist = d;
                 best_coord = (x as i32, y as i32);
             }
         }
     }
+    (best_dist, best_coord)
+}
+
+/// Where the board is on an image.
+struct Location {
+    centre: (i32, i32),
+    /// Relative to the profile's tile size.
+    scale: f32,
+    /// How clearly the board was found, 0 right at the threshold up to 1 for a perfect match.
+    quality: f32,
+}
+
+const GOLD_THRESHOLD: f32 = 100.0;
+/// Block size of the downsampled image the coarse search runs on.
+const COARSE_BLOCK: usize = 4;
+/// How far a block's mean colour may be from the template's for the block to be searched.
+const COARSE_THRESHOLD: f32 = 30.0;
+
+/// Finds the gold marble at the centre of the board. Looks near the last position first, then in the
+/// blocks of a downsampled screen whose colour is close to gold's, and only then everywhere. Returns
+/// the centre with its distance from the template, or the best distance there was if none is close
+/// enough.
+fn locate_gold(image: &Image<SRGB>, gold: &[[SRGB; 5]; 3], last: Option<(i32, i32)>) -> Result<((i32, i32), f32), f32> {
+    if let Some((x, y)) = last {
+        let (dist, coord) = search_gold(image, gold, (x - 4, x + 5), (y - 4, y + 5));
+        if dist <= GOLD_THRESHOLD { return Ok((coord, dist)) }
+    }
+
+    let best = coarse_gold(image, gold);
+    if best.0 <= GOLD_THRESHOLD { return Ok((best.1, best.0)) }
+
+    let (dist, coord) = search_gold(image, gold, (0, image.w as i32), (0, image.h as i32));
+    if dist <= GOLD_THRESHOLD { Ok((coord, dist)) } else { Err(dist) }
+}
+
+/// Best gold match around the `COARSE_BLOCK` blocks whose mean colour is close to the template's.
+fn coarse_gold(image: &Image<SRGB>, gold: &[[SRGB; 5]; 3]) -> (f32, (i32, i32)) {
+    let mean = |pixels: &mut Iterator<Item=&SRGB>| -> SRGB {
+        let (mut r, mut g, mut b, mut n) = (0u32, 0u32, 0u32, 0u32);
+        for p in pixels {
+            r += p.r as u32;
+            g += p.g as u32;
+            b += p.b as u32;
+            n += 1;
+        }
+        SRGB{r: (r / n) as u8, g: (g / n) as u8, b: (b / n) as u8}
+    };
+    let gold_mean = mean(&mut gold.iter().flat_map(|row| row.iter()));
+
+    let mut best = (::std::f32::MAX, (0, 0));
+    for by in 0..image.h / COARSE_BLOCK {
+        for bx in 0..image.w / COARSE_BLOCK {
+            let block = mean(&mut (0..COARSE_BLOCK * COARSE_BLOCK).map(|i| {
+                let (x, y) = (bx * COARSE_BLOCK + i % COARSE_BLOCK, by * COARSE_BLOCK + i / COARSE_BLOCK);
+                &image.data[y * image.w + x]
+            }));
+            if block.eucl_dist(&gold_mean) > COARSE_THRESHOLD { continue }
+
+            let (x, y) = ((bx * COARSE_BLOCK) as i32, (by * COARSE_BLOCK) as i32);
+            let margin = COARSE_BLOCK as i32;
+            let found = search_gold(image, gold, (x - margin, x + 2 * margin), (y - margin, y + 2 * margin));
+            if found.0 < best.0 { best = found }
+        }
+    }
+    best
+}
+
+/// Gradient magnitude of a cheap luma, the edge measure the grid search works on.
+fn edge_energy(image: &Image<SRGB>) -> Image<f32> {
+    let luma = |p: &SRGB| (p.r as i32 * 54 + p.g as i32 * 183 + p.b as i32 * 19) >> 8;
+    let (w, h) = (image.w, image.h);
+    let mut data = vec![0.0; w * h];
+    if w < 2 || h < 2 { return Image{w, h, data} }
+    for y in 0..h - 1 {
+        for x in 0..w - 1 {
+            let l = luma(&image.data[y * w + x]);
+            let dx = (luma(&image.data[y * w + x + 1]) - l).abs();
+            let dy = (luma(&image.data[(y + 1) * w + x]) - l).abs();
+            data[y * w + x] = (dx + dy) as f32;
+        }
+    }
+    Image{w, h, data}
+}
+
+/// Mean over a (2 * radius + 1) square around each pixel, clamped at the borders.
+fn box_blur(image: &Image<f32>, radius: usize) -> Image<f32> {
+    if image.w == 0 || image.h == 0 { return image.clone() }
+    let pass = |data: &[f32], len: usize, lines: usize, step: usize, stride: usize| -> Vec<f32> {
+        let mut out = vec![0.0; data.len()];
+        for line in 0..lines {
+            let at = |i: usize| data[line * stride + i * step];
+            let mut sum: f32 = (0..radius + 1).map(|i| at(std::cmp::min(i, len - 1))).sum::<f32>()
+                + at(0) * radius as f32;
+            for i in 0..len {
+                out[line * stride + i * step] = sum / (2 * radius + 1) as f32;
+                sum += at(std::cmp::min(i + radius + 1, len - 1)) - at(i.saturating_sub(radius));
+            }
+        }
+        out
+    };
+    let rows = pass(&image.data, image.w, image.h, 1, image.w);
+    let data = pass(&rows, image.h, image.w, image.w, 1);
+    Image{w: image.w, h: image.h, data}
+}
+
+/// Mean of `COARSE_BLOCK` sized blocks.
+fn downsample(image: &Image<f32>) -> Image<f32> {
+    let (w, h) = (image.w / COARSE_BLOCK, image.h / COARSE_BLOCK);
+    let mut data = vec![0.0; w * h];
+    for y in 0..h * COARSE_BLOCK {
+        for x in 0..w * COARSE_BLOCK {
+            data[(y / COARSE_BLOCK) * w + x / COARSE_BLOCK] += image.data[y * image.w + x];
+        }
+    }
+    let n = (COARSE_BLOCK * COARSE_BLOCK) as f32;
+    Image{w, h, data: data.into_iter().map(|e| e / n).collect()}
+}
+
+/// Board scale from the spacing of the marble rows: the autocorrelation of the vertical edge profile
+/// of the whole screen peaks at the row height, `tile_h` at scale 1.
+fn detect_row_scale(energy: &Image<f32>, tile_h: f32) -> f32 {
+    let profile: Vec<f32> = (0..energy.h).map(|y| energy.data[y * energy.w..(y + 1) * energy.w].iter().sum()).collect();
+    let mean = profile.iter().sum::<f32>() / profile.len() as f32;
+    let profile: Vec<f32> = profile.into_iter().map(|p| p - mean).collect();
+    let (min_lag, max_lag) = ((tile_h * MIN_SCALE) as usize, (tile_h * MAX_SCALE) as usize);
+    best_period(|lag| autocorrelation(&profile, lag), min_lag, max_lag) / tile_h
+}
+
+/// How much more edge energy there is at the cells of a board centred at (x, y) than in the gaps
+/// between them, as a ratio. Offsets are in tiles; `None` if the board doesn't fit in the image.
+fn grid_contrast(energy: &Image<f32>, x: f32, y: f32, tile_w: f32, tile_h: f32) -> Option<f32> {
+    let at = |q: f32, r: f32| -> Option<f32> {
+        let (px, py) = ((x + q * tile_w).round(), (y - r * tile_h).round());
+        if px < 0.0 || py < 0.0 || px >= energy.w as f32 || py >= energy.h as f32 { return None }
+        Some(energy.data[py as usize * energy.w + px as usize])
+    };
+    let (mut cells, mut gaps) = (0.0, 0.0);
+    for &(q, r) in GRID_OFFSETS.iter() {
+        cells += at(q, r)?;
+        // The middle of the triangle between this cell, the next one and the one above both.
+        gaps += at(q + 0.5, r + 1.0 / 3.0)?;
+    }
+    Some(cells / (gaps + 1.0))
+}
+
+lazy_static! {
+    /// Screen offsets of the cells from the centre, in tiles, up being positive.
+    static ref GRID_OFFSETS: Vec<(f32, f32)> = board_cells().iter().map(|&(x, y)| {
+        let r = y as f32 - 6.0;
+        (x as f32 - 6.0 + r / 2.0, r)
+    }).collect();
+}
+
+/// `detect_scale` can settle on a fraction or a multiple of the pitch when the row through the centre
+/// has little regular structure, and is only as exact as that row. Instead, takes the scale at which
+/// the grid around the centre stands out most, with the edges blurred as much as `scale` suggests, and
+/// its `grid_contrast`. `None` if no board centred there fits on the image.
+fn confirm_scale(image: &Image<SRGB>, (x, y): (i32, i32), tile: (f32, f32), scale: f32) -> (f32, Option<f32>) {
+    let (energy, (cx, cy)) = energy_around(image, (x, y), tile, (tile.0 * scale * 0.2) as usize);
+    let steps = ((MAX_SCALE - MIN_SCALE) / SCALE_STEP) as usize;
+    // Past the largest board that fits, none do.
+    (0..steps + 1).map(|i| MIN_SCALE + i as f32 * SCALE_STEP)
+        .map(|s| (s, grid_contrast(&energy, cx, cy, tile.0 * s, tile.1 * s)))
+        .take_while(|&(_, c)| c.is_some())
+        .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+        .map_or((scale, None), |best| best)
+}
+
+/// Resolution of `confirm_scale`.
+const SCALE_STEP: f32 = 0.005;
+
+/// Edge energy blurred by `blur`, of the part of the image centred on (x, y) that holds the largest
+/// board centred there that fits on the image, with where the centre is in it. Cells reach 6 tiles out
+/// and `grid_contrast`'s gaps half a tile sideways and a third of a row up beyond that.
+fn energy_around(image: &Image<SRGB>, (x, y): (i32, i32), tile: (f32, f32), blur: usize) -> (Image<f32>, (f32, f32)) {
+    let reach_x = num::clamp(std::cmp::min(x, image.w as i32 - 1 - x), 0, image.w as i32);
+    let reach_y = num::clamp(std::cmp::min(y, image.h as i32 - 1 - y), 0, image.h as i32);
+    let (board_w, board_h) = (6.5 * tile.0, (6.0 + 1.0 / 3.0) * tile.1);
+    let margin = blur as i32 + 1;
+    let reach_x = std::cmp::min(reach_x, (reach_y as f32 * board_w / board_h) as i32 + margin);
+    let reach_y = std::cmp::min(reach_y, (reach_x as f32 * board_h / board_w) as i32 + margin);
+    let (w, h) = (2 * reach_x + 1, 2 * reach_y + 1);
+    let crop = Image{w: w as usize, h: h as usize, data: cut_patch(image, x, y, w, h)};
+    (box_blur(&edge_energy(&crop), blur), (reach_x as f32, reach_y as f32))
+}
+
+/// How many times more edge energy the cells need than the gaps for the grid to count as found.
+const GRID_THRESHOLD: f32 = 1.5;
+
+/// Finds the board from its hexagonal grid of tiles alone, for when gold is gone or covered. Tries
+/// every centre on a downsampled edge map, then refines around the best at full resolution. `tile` is
+/// the tile size at scale 1.
+fn locate_grid(image: &Image<SRGB>, tile: (f32, f32), scale_hint: Option<f32>) -> Option<Location> {
+    let energy = edge_energy(image);
+    let scale = scale_hint.unwrap_or_else(|| detect_row_scale(&energy, tile.1));
+    let (tile_w, tile_h) = (tile.0 * scale, tile.1 * scale);
+
+    // Compare whole marbles rather than single pixels.
+    let energy = box_blur(&energy, (tile_w * 0.2) as usize);
+    let coarse = downsample(&energy);
+    let block = COARSE_BLOCK as f32;
+    let mut best = (0.0, (0, 0));
+    for by in 0..coarse.h {
+        for bx in 0..coarse.w {
+            if let Some(c) = grid_contrast(&coarse, bx as f32, by as f32, tile_w / block, tile_h / block) {
+                if c > best.0 { best = (c, (bx, by)) }
+            }
+        }
+    }
+    if best.0 < GRID_THRESHOLD { return None }
+
+    let (bx, by) = ((best.1).0 as i32 * COARSE_BLOCK as i32, (best.1).1 as i32 * COARSE_BLOCK as i32);
+    let mut refined = (0.0, (bx, by));
+    let reach = COARSE_BLOCK as i32;
+    for y in by - reach..by + 2 * reach {
+        for x in bx - reach..bx + 2 * reach {
+            if let Some(c) = grid_contrast(&energy, x as f32, y as f32, tile_w, tile_h) {
+                if c > refined.0 { refined = (c, (x, y)) }
+            }
+        }
+    }
+    Some(Location { centre: refined.1, scale, quality: 1.0 - GRID_THRESHOLD / refined.0 })
+}
+
+/// Why a board couldn't be read.
+#[derive(Debug)]
+pub enum OcrError {
+    NoDisplay(io::Error),
+    Capture(io::Error),
+    /// Neither gold nor the tile grid was found. Has the gold template's best distance from the screen.
+    BoardNotFound { best_distance: f32 },
+    /// Some cells of the board found at `centre` are too close to the edge of the screen to read, as
+    /// when the scale was misdetected.
+    OffScreen { centre: (i32, i32), scale: f32 },
+    Font(String),
+    /// The board was read, but too unsure of some cells to use. Has the lowest confidence.
+    LowConfidence(f32),
+    /// The board was read, but no correction of it has marble counts a game could have, or one that
+    /// can be solved.
+    InconsistentCounts,
+}
 
-    if best_dist > 100.0 {
-        return None;
+impl fmt::Display for OcrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OcrError::NoDisplay(ref e) => write!(f, "Couldn't find primary display: {}", e),
+            OcrError::Capture(ref e) => write!(f, "Couldn't capture the screen: {}", e),
+            OcrError::BoardNotFound { best_distance } =>
+                write!(f, "Board not visible, gold is {:.0} from its template at best", best_distance),
+            OcrError::OffScreen { centre, scale } =>
+                write!(f, "Board at {:?} with scale {:.2} doesn't fit on the screen", centre, scale),
+            OcrError::Font(ref e) => write!(f, "{}", e),
+            OcrError::LowConfidence(confidence) => write!(f, "Not sure about the board (confidence {:.2})", confidence),
+            OcrError::InconsistentCounts => write!(f, "The marbles read don't add up to a game, even corrected"),
+        }
     }
+}
 
-    // println!("Best guess for board center {} {:?}", best_dist, best_coord);
-    let (gold_x, gold_y) = best_coord;
+/// Byte order of a frame's pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Bgra,
+    Rgba,
+}
 
-    let rows = board_rows();
+/// A part of the screen, by its top left corner and size.
+pub type Region = ((i32, i32), (usize, usize));
+
+/// A captured frame as the backend hands it over: rows of 4 byte pixels, each starting `stride` bytes
+/// after the last, which can be more than the pixels need. It can be just a part of the screen, with
+/// its top left corner at `origin`.
+#[derive(Clone)]
+pub struct Frame {
+    data: Vec<u8>,
+    width: usize,
+    height: usize,
+    stride: usize,
+    format: PixelFormat,
+    origin: (i32, i32),
+    screen: (usize, usize),
+}
 
-    let mut board: Board = Board::new(
-        [[Marble::Empty;13];13],
-        gold_x as f32 / screen_w as f32,
-        gold_y as f32 / screen_h as f32,
-        TILE_WIDTH as f32 / screen_w as f32,
-        TILE_HEIGHT as f32 / screen_h as f32,
-    );
+/// Checks that rows of `width` pixels fit in the stride and `height` of them in the data.
+fn check_layout(len: usize, width: usize, height: usize, stride: usize) -> io::Result<()> {
+    let invalid = |e: String| Err(io::Error::new(io::ErrorKind::InvalidData, e));
+    if stride < width * 4 {
+        return invalid(format!("stride {} is too short for rows of {} pixels", stride, width));
+    }
+    if height > 0 && len < (height - 1) * stride + width * 4 {
+        return invalid(format!("{} bytes is too short for {} rows {} bytes apart", len, height, stride));
+    }
+    Ok(())
+}
+
+impl Frame {
+    /// A frame of the whole screen. Checks that the rows fit in the stride and the stride in the data.
+    pub fn new(data: Vec<u8>, width: usize, height: usize, stride: usize, format: PixelFormat) -> io::Result<Frame> {
+        check_layout(data.len(), width, height, stride)?;
+        Ok(Frame { data, width, height, stride, format, origin: (0, 0), screen: (width, height) })
+    }
+
+    /// A frame with no padding after its rows.
+    pub fn packed(data: Vec<u8>, width: usize, height: usize, format: PixelFormat) -> io::Result<Frame> {
+        Frame::new(data, width, height, width * 4, format)
+    }
+
+    /// The part in `region` of a screen laid out in `bytes` as `new` takes them, clipped to the screen,
+    /// as a packed frame. Only the pixels of the region are copied, all of them without one.
+    pub fn cut(bytes: &[u8], width: usize, height: usize, stride: usize, format: PixelFormat, region: Option<Region>)
+               -> io::Result<Frame> {
+        check_layout(bytes.len(), width, height, stride)?;
+        let ((x, y), (w, h)) = region.unwrap_or(((0, 0), (width, height)));
+        let clip = |lo: i32, len: usize, size: usize| {
+            let start = num::clamp(lo, 0, size as i32) as usize;
+            (start, num::clamp(lo + len as i32, start as i32, size as i32) as usize)
+        };
+        let ((x0, x1), (y0, y1)) = (clip(x, w, width), clip(y, h, height));
+        let mut data = Vec::with_capacity((x1 - x0) * (y1 - y0) * 4);
+        for row in y0..y1 {
+            data.extend_from_slice(&bytes[row * stride + x0 * 4..row * stride + x1 * 4]);
+        }
+        let (w, h) = (x1 - x0, y1 - y0);
+        Ok(Frame { data, width: w, height: h, stride: w * 4, format, origin: (x0 as i32, y0 as i32), screen: (width, height) })
+    }
+
+    /// The part of this frame in `region` of the screen, see `cut`.
+    pub fn region(&self, region: Option<Region>) -> Frame {
+        let (ox, oy) = self.origin;
+        let region = region.map(|((x, y), size)| ((x - ox, y - oy), size));
+        let mut frame = Frame::cut(&self.data, self.width, self.height, self.stride, self.format, region)
+            .expect("Frame doesn't fit its own layout.");
+        frame.origin = (frame.origin.0 + ox, frame.origin.1 + oy);
+        frame.screen = self.screen;
+        frame
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    /// Where the frame's top left corner is on the screen.
+    pub fn origin(&self) -> (i32, i32) {
+        self.origin
+    }
+
+    /// Size of the whole screen the frame is of.
+    pub fn screen_size(&self) -> (usize, usize) {
+        self.screen
+    }
+
+    /// The pixels of each row, without the padding.
+    pub fn rows(&self) -> FrameRows {
+        FrameRows { frame: self, y: 0 }
+    }
+
+    pub fn to_image(&self) -> Image<SRGB> {
+        let pixel: fn(&[u8]) -> SRGB = match self.format {
+            PixelFormat::Bgra => |p| SRGB{r: p[2], g: p[1], b: p[0]},
+            PixelFormat::Rgba => |p| SRGB{r: p[0], g: p[1], b: p[2]},
+        };
+        let data = self.rows().flat_map(|row| row.chunks(4).map(pixel)).collect();
+        Image{w: self.width, h: self.height, data}
+    }
+}
+
+pub struct FrameRows<'a> {
+    frame: &'a Frame,
+    y: usize,
+}
+
+impl<'a> Iterator for FrameRows<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.y >= self.frame.height { return None }
+        let start = self.y * self.frame.stride;
+        self.y += 1;
+        Some(&self.frame.data[start..start + self.frame.width * 4])
+    }
+}
+
+/// Where the frames the board is read from come from.
+pub trait Screen {
+    /// The whole screen, or only its part in `region`, clipped to the screen.
+    fn capture(&mut self, region: Option<Region>) -> Result<Frame, OcrError>;
+}
+
+/// The primary display.
+pub struct Desktop;
+
+impl Screen for Desktop {
+    fn capture(&mut self, region: Option<Region>) -> Result<Frame, OcrError> {
+        capture_screen(region)
+    }
+}
 
-    for (i, r) in rows.iter().enumerate() {
+/// A screenshot saved as an image file, shown as if it were the screen.
+pub struct Screenshot {
+    frame: Frame,
+}
+
+impl Screenshot {
+    pub fn load(path: &str) -> Result<Screenshot, String> {
+        let rgba = image::open(path).map_err(|e| e.description().to_string())?.to_rgba();
+        let (w, h) = rgba.dimensions();
+        let frame = Frame::packed(rgba.into_raw(), w as usize, h as usize, PixelFormat::Rgba).map_err(|e| e.to_string())?;
+        Ok(Screenshot { frame })
+    }
+}
+
+impl Screen for Screenshot {
+    fn capture(&mut self, region: Option<Region>) -> Result<Frame, OcrError> {
+        Ok(self.frame.region(region))
+    }
+}
+
+fn capture_screen(region: Option<Region>) -> Result<Frame, OcrError> {
+    let display = Display::primary().map_err(OcrError::NoDisplay)?;
+    let capturer = Capturer::new(display).map_err(OcrError::Capture)?;
+    capture(capturer, region)
+}
+
+/// Tiles around the board that `BoardReader::region` takes in too, so a board that moved a little is
+/// still inside.
+const REGION_MARGIN: f32 = 1.0;
+
+/// Cells read with less confidence than this make the whole read shaky.
+pub const LOW_CONFIDENCE: f32 = 0.15;
+
+/// A board read off the screen with how sure each cell is.
+pub struct BoardRead {
+    pub board: Board,
+    /// Recognition of each cell, by array indices of `board`.
+    pub cells: Vec<((usize, usize), Recognition)>,
+    /// The lowest confidence of any cell.
+    pub confidence: f32,
+    /// Cells under `LOW_CONFIDENCE`, by array indices of `board`.
+    pub low_confidence: Vec<(usize, usize)>,
+    /// Marbles drawn bright that the rules say can't be selected or the other way around.
+    pub free_disagreements: Vec<(usize, usize)>,
+    /// How clearly the board was found, 0 right at the threshold up to 1 for a perfect match.
+    pub localisation: f32,
+    /// Board centre in pixels.
+    pub centre: (i32, i32),
+    /// Size of the board relative to its profile.
+    pub scale: f32,
+}
+
+/// Marbles dimmer than this fraction of the brightest marble of their kind are taken as dimmed.
+const FREE_BRIGHTNESS: f32 = 0.8;
+
+impl BoardRead {
+    /// `seen` has the brightest each kind of marble has been seen on earlier boards, 0 if never.
+    pub fn new(board: Board, mut cells: Vec<((usize, usize), Recognition)>, seen: &[f32; 14]) -> BoardRead {
+        let confidence = cells.iter().map(|&(_, ref r)| r.certainty()).fold(1.0, f32::min);
+        let low_confidence = cells.iter()
+            .filter(|&&(_, ref r)| r.certainty() < LOW_CONFIDENCE)
+            .map(|&(pos, _)| pos)
+            .collect();
+
+        // Marbles differ in brightness, so each is compared with the brightest of its kind seen before
+        // or on this board. A kind whose marbles here all look alike and that hasn't been seen before
+        // can't be told either way.
+        for &marble in MARBLE_VALUES.iter() {
+            let kind: Vec<usize> = (0..cells.len()).filter(|&i| cells[i].1.marble() == marble).collect();
+            let (dimmest, brightest) = kind.iter().map(|&i| cells[i].1.brightness)
+                .fold((std::f32::MAX, 0.0f32), |(lo, hi), b| (lo.min(b), hi.max(b)));
+            let reference = brightest.max(seen[marble as usize]);
+            let known = seen[marble as usize] > 0.0 || dimmest < FREE_BRIGHTNESS * brightest;
+            for i in kind {
+                cells[i].1.appears_free = if known { Some(cells[i].1.brightness >= FREE_BRIGHTNESS * reference) } else { None };
+            }
+        }
+
+        let least_metal = board.least_metal();
+        let free_disagreements = cells.iter().filter(|&&((x, y), ref r)| {
+            let marble = board.board[y][x];
+            let metal = (marble as usize) <= Marble::Gold as usize;
+            let selectable = board.is_free(x, y) && (!metal || marble == least_metal);
+            marble != Marble::Empty && r.appears_free.map_or(false, |free| free != selectable)
+        }).map(|&(pos, _)| pos).collect();
+
+        BoardRead { board, cells, confidence, low_confidence, free_disagreements, localisation: 1.0, centre: (0, 0), scale: 1.0 }
+    }
+}
+
+/// Reads boards off the screen, remembering where the board was to find it faster next time.
+pub struct BoardReader {
+    pub metric: Metric,
+    /// Colour model fused with the glyph scores, if one has been trained.
+    pub colours: Option<ColourModel>,
+    profile: Profile,
+    last_centre: Option<(i32, i32)>,
+    last_scale: Option<f32>,
+    /// Brightest each kind of marble has been read as, by `Marble as usize`.
+    brightest: [f32; 14],
+    /// The screen `read` last captured, for `save_debug_image`.
+    last_frame: Option<Frame>,
+}
+
+impl BoardReader {
+    pub fn new() -> BoardReader {
+        BoardReader::with_profile(Profile::opus_magnum())
+    }
+
+    pub fn with_profile(profile: Profile) -> BoardReader {
+        BoardReader {
+            metric: Metric::Chamfer, colours: None, profile,
+            last_centre: None, last_scale: None, brightest: [0.0; 14], last_frame: None,
+        }
+    }
+
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    /// Reads with the glyphs in `dir` instead of the profile's. See `load_font_dir`.
+    pub fn use_font_dir(&mut self, dir: &str) -> Result<(), OcrError> {
+        self.profile.glyphs = load_font_dir(dir).map_err(OcrError::Font)?;
+        Ok(())
+    }
+
+    /// Reads the board off the screen. Once it has been found, only the part of the screen around where
+    /// it was is captured and searched, and all of it only if the board isn't there or was found away
+    /// from where it was.
+    pub fn read<S: Screen>(&mut self, screen: &mut S) -> Result<BoardRead, OcrError> {
+        if let (Some(region), Some(last_centre), Some(last_scale)) = (self.region(), self.last_centre, self.last_scale) {
+            let frame = screen.capture(Some(region))?;
+            let read = self.read_region(&frame.to_image(), frame.origin(), frame.screen_size());
+            // A board that moved can leave a grid-like part of itself behind in the region.
+            let stayed = match read {
+                Ok(ref read) => {
+                    let (dx, dy) = ((read.centre.0 - last_centre.0).abs() as f32, (read.centre.1 - last_centre.1).abs() as f32);
+                    let (tile_w, tile_h) = (self.profile.tile.0 * last_scale, self.profile.tile.1 * last_scale);
+                    dx <= REGION_MARGIN * tile_w && dy <= REGION_MARGIN * tile_h
+                },
+                Err(_) => false,
+            };
+            if stayed {
+                self.last_frame = Some(frame);
+                return read;
+            }
+            self.last_centre = Some(last_centre);
+            self.last_scale = Some(last_scale);
+        }
+        let frame = screen.capture(None)?;
+        let read = self.read_region(&frame.to_image(), (0, 0), frame.screen_size());
+        self.last_frame = Some(frame);
+        read
+    }
+
+    /// The corner and size of the part of the screen the board was in at the last read, with a margin.
+    /// `None` before it has been found.
+    pub fn region(&self) -> Option<Region> {
+        let ((x, y), scale) = (self.last_centre?, self.last_scale?);
+        let reach = 6.5 + REGION_MARGIN;
+        let (reach_x, reach_y) = (reach * self.profile.tile.0 * scale, reach * self.profile.tile.1 * scale);
+        Some(((x - reach_x as i32, y - reach_y as i32), ((2.0 * reach_x) as usize, (2.0 * reach_y) as usize)))
+    }
+
+    /// Writes the screen of the last `read`, as much of it as was captured, annotated with how it was
+    /// read, as a PNG. See `debug_image`.
+    pub fn save_debug_image(&self, read: &BoardRead, path: &str) -> Result<(), String> {
+        let frame = self.last_frame.as_ref().ok_or("Nothing has been read yet.".to_string())?;
+        let (w, h) = frame.screen_size();
+        let mut screen = Image{w, h, data: vec![SRGB{r: 0, g: 0, b: 0}; w * h]};
+        blit(&mut screen, frame.origin().0, frame.origin().1, &frame.to_image());
+        save_image(&debug_image(&screen, read, &scaled_font(&self.profile.glyphs, read.scale)), path)
+    }
+
+    /// Finds the board on an image whose top left corner is at `origin` on the screen. The location is
+    /// on the image, what's remembered for next time on the screen.
+    fn locate(&mut self, image: &Image<SRGB>, (ox, oy): (i32, i32)) -> Result<Location, OcrError> {
+        // Gold gives the centre to the pixel while it's on the board, if the tile grid agrees it's the
+        // centre. Otherwise the grid alone, and gold only if there's no grid to be found.
+        let last = self.last_centre.map(|(x, y)| (x - ox, y - oy));
+        let tile = self.profile.tile;
+        let location = match locate_gold(image, &self.profile.gold, last) {
+            Ok(((x, y), dist)) => {
+                let (scale, contrast) = confirm_scale(image, (x, y), tile, detect_scale(image, x, y, tile.0));
+                let gold = Location { centre: (x, y), scale, quality: 1.0 - dist / GOLD_THRESHOLD };
+                match contrast {
+                    Some(contrast) if contrast >= GRID_THRESHOLD => gold,
+                    _ => locate_grid(image, tile, self.last_scale).unwrap_or(gold),
+                }
+            },
+            Err(best_distance) => locate_grid(image, tile, self.last_scale)
+                .ok_or(OcrError::BoardNotFound { best_distance })?,
+        };
+        self.last_centre = Some((location.centre.0 + ox, location.centre.1 + oy));
+        self.last_scale = Some(location.scale);
+        Ok(location)
+    }
+
+    /// Reads an image instead of the screen.
+    pub fn read_image(&mut self, desktop_image: &Image<SRGB>) -> Result<BoardRead, OcrError> {
+        self.read_region(desktop_image, (0, 0), (desktop_image.w, desktop_image.h))
+    }
+
+    /// Reads the part of a `screen_w`×`screen_h` screen at `origin` that's in the image.
+    fn read_region(&mut self, desktop_image: &Image<SRGB>, (ox, oy): (i32, i32), (screen_w, screen_h): (usize, usize))
+                   -> Result<BoardRead, OcrError> {
+        let location = self.locate(desktop_image, (ox, oy))?;
+        let ((centre_x, centre_y), scale) = (location.centre, location.scale);
+        let screen_centre = (centre_x + ox, centre_y + oy);
+
+        let (tile_w, tile_h) = (self.profile.tile.0 * scale, self.profile.tile.1 * scale);
+        let font = Font::new(&self.profile.glyphs, scale);
+        let (glyph_w, glyph_h) = (font.glyphs[0].1.w as i32, font.glyphs[0].1.h as i32);
+        let centres = cell_centres((centre_x, centre_y), (tile_w, tile_h));
+        let (image_w, image_h) = (desktop_image.w as i32, desktop_image.h as i32);
+        let fits = |&(_, (x, y)): &((usize, usize), (i32, i32))| {
+            x - glyph_w / 2 >= 0 && y - glyph_h / 2 >= 0
+                && x + glyph_w - glyph_w / 2 <= image_w && y + glyph_h - glyph_h / 2 <= image_h
+        };
+        if !centres.iter().all(fits) {
+            return Err(OcrError::OffScreen { centre: screen_centre, scale });
+        }
+
+        let mut board: Board = Board::new(
+            [[Marble::Empty;13];13],
+            screen_centre.0 as f32 / screen_w as f32,
+            screen_centre.1 as f32 / screen_h as f32,
+            tile_w / screen_w as f32,
+            tile_h / screen_h as f32,
+        );
+
+        let mut cells = Vec::with_capacity(91);
+        for ((x, y), (coord_x, coord_y)) in centres {
+            let recognition = recognize_marble_at(desktop_image, coord_x, coord_y, &font, self.metric, self.colours.as_ref());
+            board.board[y][x] = recognition.marble();
+            cells.push(((x, y), recognition));
+        }
+
+        let mut read = BoardRead::new(board, cells, &self.brightest);
+        read.localisation = location.quality;
+        read.centre = screen_centre;
+        read.scale = scale;
+        for &(_, ref r) in &read.cells {
+            let marble = r.marble();
+            if marble != Marble::Empty {
+                let b = &mut self.brightest[marble as usize];
+                *b = b.max(r.brightness);
+            }
+        }
+        Ok(read)
+    }
+}
+
+/// Array indices of each cell of a board centred at `centre` with tiles of the given size, with the
+/// pixel its marble is centred on.
+pub fn cell_centres((centre_x, centre_y): (i32, i32), (tile_w, tile_h): (f32, f32)) -> Vec<((usize, usize), (i32, i32))> {
+    let mut centres = Vec::with_capacity(91);
+    for (i, r) in board_rows().iter().enumerate() {
         for x in r.x_min .. r.x_max + 1 {
             let (screen_x, screen_y) = board_pos_to_screen(x - 5, (i as i32 - 5));
-            let (coord_x, coord_y) = (gold_x + (screen_x * TILE_WIDTH) as i32 + 1, gold_y + (screen_y * TILE_HEIGHT) as i32);
-            
-            board.board[i + 1][x as usize + 1] = recognize_marble_at(&desktop_image, coord_x, coord_y);
-            // println!("{} {} {}", i, x, board.board[i + 1][x as usize + 1]);
+            let coord = (centre_x + (screen_x * tile_w) as i32 + 1, centre_y + (screen_y * tile_h) as i32);
+            centres.push(((x as usize + 1, i + 1), coord));
+        }
+    }
+    centres
+}
+
+/// Colours of the debug image.
+const DEBUG_CENTRE: SRGB = SRGB{r: 255, g: 0, b: 255};
+const DEBUG_PATCH: SRGB = SRGB{r: 0, g: 220, b: 0};
+const DEBUG_EMPTY: SRGB = SRGB{r: 110, g: 110, b: 110};
+const DEBUG_LOW_CONFIDENCE: SRGB = SRGB{r: 255, g: 40, b: 0};
+const DEBUG_TEXT: SRGB = SRGB{r: 255, g: 255, b: 255};
+/// Pixels around the thumbnails in a cell's box on the panel.
+const DEBUG_PAD: usize = 3;
+
+/// 3x5 digits and a point, a row of three bits per byte, for the confidences on the debug image.
+const DIGITS: [[u8; 5]; 11] = [
+    [7, 5, 5, 5, 7], [2, 6, 2, 2, 7], [7, 1, 7, 4, 7], [7, 1, 7, 1, 7], [5, 5, 7, 1, 1], [7, 4, 7, 1, 7],
+    [7, 4, 7, 5, 7], [7, 1, 1, 1, 1], [7, 5, 7, 5, 7], [7, 5, 7, 1, 7], [0, 0, 0, 0, 2],
+];
+
+fn put_pixel(image: &mut Image<SRGB>, x: i32, y: i32, colour: SRGB) {
+    if x >= 0 && y >= 0 && (x as usize) < image.w && (y as usize) < image.h {
+        image.data[y as usize * image.w + x as usize] = colour;
+    }
+}
+
+/// Outline of a w×h rectangle with its top left at (x, y), `thickness` pixels wide inwards.
+fn draw_rect(image: &mut Image<SRGB>, x: i32, y: i32, w: i32, h: i32, thickness: i32, colour: SRGB) {
+    for t in 0..thickness {
+        for i in t..w - t {
+            put_pixel(image, x + i, y + t, colour);
+            put_pixel(image, x + i, y + h - 1 - t, colour);
+        }
+        for j in t..h - t {
+            put_pixel(image, x + t, y + j, colour);
+            put_pixel(image, x + w - 1 - t, y + j, colour);
+        }
+    }
+}
+
+fn blit(image: &mut Image<SRGB>, x: i32, y: i32, src: &Image<SRGB>) {
+    for i in 0..src.data.len() {
+        put_pixel(image, x + (i % src.w) as i32, y + (i / src.w) as i32, src.data[i]);
+    }
+}
+
+/// Digits and points at twice their size, other characters as spaces.
+fn draw_number(image: &mut Image<SRGB>, x: i32, y: i32, text: &str, colour: SRGB) {
+    for (n, c) in text.chars().enumerate() {
+        let digit = match c {
+            '.' => &DIGITS[10],
+            _ => match c.to_digit(10) {
+                Some(d) => &DIGITS[d as usize],
+                None => continue,
+            },
+        };
+        for (row, &bits) in digit.iter().enumerate() {
+            for col in 0..3 {
+                if bits & (4 >> col) == 0 { continue }
+                for k in 0..4 {
+                    put_pixel(image, x + n as i32 * 8 + col * 2 + k % 2, y + row as i32 * 2 + k / 2, colour);
+                }
+            }
         }
     }
+}
 
-    Some(board)
+/// The screenshot with the board centre marked and a rectangle around every patch that was cut, red
+/// for low confidence cells and grey for empty ones. Below it a panel laid out like the board shows for
+/// each cell the Canny edges of its patch coloured by gradient direction, the glyph it was read as and
+/// its confidence.
+fn debug_image(image: &Image<SRGB>, read: &BoardRead, glyphs: &[(Marble, Image<bool>)]) -> Image<SRGB> {
+    let (w, h) = (glyphs[0].1.w, glyphs[0].1.h);
+    let (box_w, box_h) = (2 * w + 3 * DEBUG_PAD, h + 10 + 3 * DEBUG_PAD);
+    let (panel_w, panel_h) = (11 * box_w, 11 * box_h);
+    let canvas_w = std::cmp::max(image.w, panel_w);
+    let mut canvas = Image{w: canvas_w, h: image.h + panel_h, data: vec![SRGB{r: 0, g: 0, b: 0}; canvas_w * (image.h + panel_h)]};
+    for y in 0..image.h {
+        canvas.data[y * canvas_w..y * canvas_w + image.w].copy_from_slice(&image.data[y * image.w..(y + 1) * image.w]);
+    }
+
+    let (x, y) = read.centre;
+    for d in -12..13 {
+        for t in -1..2 {
+            put_pixel(&mut canvas, x + d, y + t, DEBUG_CENTRE);
+            put_pixel(&mut canvas, x + t, y + d, DEBUG_CENTRE);
+        }
+    }
+
+    let (tile_w, tile_h) = read.board.tile_size();
+    let centres = cell_centres(read.centre, (tile_w * image.w as f32, tile_h * image.h as f32));
+    for (&((ix, iy), (px, py)), &(_, ref r)) in centres.iter().zip(&read.cells) {
+        let low = read.low_confidence.contains(&(ix, iy));
+        let colour = if low { DEBUG_LOW_CONFIDENCE } else if r.occupancy.occupied { DEBUG_PATCH } else { DEBUG_EMPTY };
+        let (left, top) = (px - w as i32 / 2, py - h as i32 / 2);
+        draw_rect(&mut canvas, left, top, w as i32, h as i32, if low { 2 } else { 1 }, colour);
+
+        // Same geometry as the screen: rows of the board go up, every row shifted by half a box.
+        let (q, row) = (ix as f32 - 6.0, iy as f32 - 6.0);
+        let bx = ((q + row / 2.0 + 5.0) * box_w as f32) as i32;
+        let by = (image.h as f32 + (5.0 - row) * box_h as f32) as i32;
+        draw_rect(&mut canvas, bx, by, box_w as i32, box_h as i32, if low { 2 } else { 1 }, colour);
+
+        let gray = Image{w, h, data: cut_patch(image, px, py, w as i32, h as i32).into_iter().map(to_grayscale).collect()};
+        let gradients = sobel(&gray.convolute(&GAUSS));
+        let edges = canny(&gradients);
+        let thumbnail = Image{w, h, data: (0..w * h).map(|i| {
+            if edges[i] { SRGB::from(gradients[i]) } else { SRGB{r: 0, g: 0, b: 0} }
+        }).collect()};
+        let pad = DEBUG_PAD as i32;
+        blit(&mut canvas, bx + pad, by + pad, &thumbnail);
+        if let Some(&(_, ref glyph)) = glyphs.iter().find(|g| g.0 == r.marble()) {
+            let label = Image{w, h, data: glyph.data.iter().map(|&e| if e { DEBUG_TEXT } else { SRGB{r: 0, g: 0, b: 0} }).collect()};
+            blit(&mut canvas, bx + 2 * pad + w as i32, by + pad, &label);
+        }
+        draw_number(&mut canvas, bx + pad, by + 2 * pad + h as i32, &format!("{:.2}", r.certainty()), colour);
+    }
+    canvas
+}
+
+/// Writes an image as a PNG.
+fn save_image(image: &Image<SRGB>, path: &str) -> Result<(), String> {
+    let buffer: ImageBuffer<Rgb<u8>, _> = ImageBuffer::from_raw(
+        image.w as u32, image.h as u32, image.data.iter().flat_map(|p| vec![p.r, p.g, p.b]).collect::<Vec<u8>>()
+    ).ok_or("Couldn't convert image into image buffer.".to_string())?;
+    buffer.save(path).map_err(|e| format!("Couldn't save {}: {}", path, e))
+}
+
+/// A screenshot saved as an image file, for reading boards offline.
+fn load_screenshot(path: &str) -> Result<Image<SRGB>, String> {
+    Screenshot::load(path).map(|screenshot| screenshot.frame.to_image())
+}
+
+/// Screenshots with the board files that say what's on them, from command line pairs.
+fn load_labelled(args: &[String], usage: &str) -> Vec<(String, Image<SRGB>, Board)> {
+    if args.is_empty() || args.len() % 2 != 0 {
+        println!("usage: sigmar_bot {}", usage);
+        std::process::exit(1);
+    }
+    args.chunks(2).map(|pair| {
+        let screenshot = load_screenshot(&pair[0]).unwrap_or_else(|e| panic!("{}: {}", pair[0], e));
+        let mut text = String::new();
+        File::open(&pair[1]).and_then(|mut f| f.read_to_string(&mut text)).unwrap_or_else(|e| panic!("{}: {}", pair[1], e));
+        let truth = Board::from_text(&text).unwrap_or_else(|e| panic!("{}: {}", pair[1], e));
+        (pair[0].clone(), screenshot, truth)
+    }).collect()
+}
+
+/// Recognitions of the cells of each screenshot that hold a marble and were read as holding one, with
+/// the right marble.
+fn labelled_cells(labelled: &[(String, Image<SRGB>, Board)], new_reader: &Fn() -> BoardReader)
+                  -> Vec<Vec<(Marble, Recognition)>> {
+    labelled.iter().map(|&(ref path, ref screenshot, ref truth)| {
+        match new_reader().read_image(screenshot) {
+            Err(e) => {
+                println!("{}: {}", path, e);
+                Vec::new()
+            },
+            Ok(read) => read.cells.into_iter()
+                .filter(|&((x, y), ref r)| truth.board[y][x] != Marble::Empty && r.occupancy.occupied)
+                .map(|((x, y), r)| (truth.board[y][x], r))
+                .collect(),
+        }
+    }).collect()
+}
+
+/// `train-colours <out> <screenshot> <board file> ...`: learns a colour model from labelled screenshots.
+pub fn train_colours(args: &[String]) {
+    let usage = "train-colours <out file> <screenshot> <board file> [<screenshot> <board file> ...]";
+    if args.is_empty() { load_labelled(&[], usage); }
+    let cells = labelled_cells(&load_labelled(&args[1..], usage), &BoardReader::new);
+    let samples: Vec<(Marble, Vec<f32>)> = cells.into_iter().flat_map(|c| c)
+        .map(|(marble, r)| (marble, r.histogram))
+        .collect();
+    let model = ColourModel::train(&samples);
+    model.save(&args[0]).expect("Couldn't write colour model.");
+    println!("Wrote colours of {} marbles from {} samples to {}", model.prototypes.len(), samples.len(), args[0]);
+}
+
+/// Fraction of a marble's samples that need an edge at a pixel for its trained glyph to have one.
+const FONT_VOTE: f32 = 0.5;
+
+/// Canny edges of a patch, as `recognize_marble_at` finds them.
+fn patch_edges(pixels: &[SRGB], w: usize, h: usize) -> Image<bool> {
+    let gray = Image{w, h, data: pixels.iter().map(|&p| to_grayscale(p)).collect()};
+    canny(&sobel(&gray.convolute(&GAUSS)))
+}
+
+/// `edges` moved by (dx, dy), with what moves in from outside empty.
+fn shift_edges(edges: &Image<bool>, dx: i32, dy: i32) -> Image<bool> {
+    let (w, h) = (edges.w as i32, edges.h as i32);
+    let data = (0..w * h).map(|i| {
+        let (x, y) = (i % w - dx, i / w - dy);
+        x >= 0 && y >= 0 && x < w && y < h && edges[(y * w + x) as usize]
+    }).collect();
+    Image{w: edges.w, h: edges.h, data}
+}
+
+/// Pixels set in at least `FONT_VOTE` of the samples.
+fn vote(samples: &[&Image<bool>]) -> Image<bool> {
+    let (w, h) = (samples[0].w, samples[0].h);
+    let needed = FONT_VOTE * samples.len() as f32;
+    let data = (0..w * h).map(|i| samples.iter().filter(|s| s[i]).count() as f32 >= needed).collect();
+    Image{w, h, data}
+}
+
+/// A glyph per marble from edge samples of the glyph's size, by per-pixel vote. Samples cut a pixel or
+/// two off are shifted onto a first vote before the final one.
+fn train_glyphs(samples: &[(Marble, Image<bool>)]) -> Vec<(Marble, Image<bool>)> {
+    MARBLE_VALUES.iter().filter_map(|&marble| {
+        let own: Vec<&Image<bool>> = samples.iter().filter(|s| s.0 == marble).map(|s| &s.1).collect();
+        if own.is_empty() { return None }
+        let first = Template::new(marble, &vote(&own));
+        let aligned: Vec<Image<bool>> = own.iter().map(|&sample| {
+            let mut best = (0, 0, 0);
+            for dy in -CHAMFER_SHIFT..CHAMFER_SHIFT + 1 {
+                for dx in -CHAMFER_SHIFT..CHAMFER_SHIFT + 1 {
+                    let d = &first.distance;
+                    let near = (0..sample.data.len()).filter(|&i| sample[i]).filter(|&i| {
+                        let (x, y) = ((i % sample.w) as i32 + dx, (i / sample.w) as i32 + dy);
+                        x >= 0 && y >= 0 && x < d.w as i32 && y < d.h as i32 && d[y as usize * d.w + x as usize] <= 1
+                    }).count();
+                    if near > best.2 || (near == best.2 && dx * dx + dy * dy < best.0 * best.0 + best.1 * best.1) {
+                        best = (dx, dy, near);
+                    }
+                }
+            }
+            shift_edges(sample, best.0, best.1)
+        }).collect();
+        Some((marble, vote(&aligned.iter().collect::<Vec<_>>())))
+    }).collect()
+}
+
+/// `train-font <out dir> <screenshot> <board file> ...`: new glyphs from the labelled cells of
+/// screenshots, written as `<out dir>/<Marble>.png`. Marbles without samples keep the current glyph.
+/// Then scores every sample against every new glyph to show how well they separate.
+pub fn train_font(args: &[String]) {
+    let usage = "train-font <out dir> <screenshot> <board file> [<screenshot> <board file> ...]";
+    if args.is_empty() { load_labelled(&[], usage); }
+    let labelled = load_labelled(&args[1..], usage);
+    let (glyph_w, glyph_h) = (GLYPH_WIDTH, GLYPH_HEIGHT);
+
+    let mut samples = Vec::new();
+    for &(ref path, ref screenshot, ref truth) in &labelled {
+        let reader = &mut BoardReader::new();
+        let Location { centre, scale, .. } = match reader.locate(screenshot, (0, 0)) {
+            Ok(found) => found,
+            Err(e) => { println!("{}: {}", path, e); continue },
+        };
+        let tile = (reader.profile.tile.0 * scale, reader.profile.tile.1 * scale);
+        // The same patch as `recognize_marble_at` cuts with the font at this scale.
+        let (w, h) = ((glyph_w as f32 * scale).round() as i32, (glyph_h as f32 * scale).round() as i32);
+        for ((x, y), (px, py)) in cell_centres(centre, tile) {
+            let marble = truth.board[y][x];
+            let inside = px >= w / 2 && py >= h / 2 && px + w < screenshot.w as i32 && py + h < screenshot.h as i32;
+            if marble == Marble::Empty || !inside { continue }
+            let edges = patch_edges(&cut_patch(screenshot, px, py, w, h), w as usize, h as usize);
+            samples.push((marble, resize_edges(&edges, glyph_w, glyph_h)));
+        }
+    }
+
+    let trained = train_glyphs(&samples);
+    std::fs::create_dir_all(&args[0]).expect("Couldn't create font directory.");
+    for &(marble, ref current) in FONT.iter() {
+        let glyph = match trained.iter().find(|g| g.0 == marble) {
+            Some(&(_, ref glyph)) => glyph,
+            None => { println!("No samples of {}, keeping its glyph", marble.to_string()); current },
+        };
+        let path = Path::new(&args[0]).join(format!("{}.png", marble.to_string()));
+        let image: ImageBuffer<Luma<u8>, _> = ImageBuffer::from_raw(
+            glyph.w as u32, glyph.h as u32, glyph.data.iter().map(|&e| if e { 255 } else { 0 }).collect::<Vec<u8>>()
+        ).expect("Couldn't convert glyph into image buffer.");
+        image.save(&path).unwrap_or_else(|e| panic!("Couldn't save {}: {}", path.display(), e));
+    }
+
+    // Mean Chamfer score of each marble's samples against each new glyph, and how many of them some
+    // other glyph scores at least as well.
+    let templates: Vec<Template> = trained.iter().map(|&(m, ref g)| Template::new(m, g)).collect();
+    println!("{:>8} {:>8} {:>8} {:>8} {:>8} {:>8}", "marble", "samples", "own", "closest", "score", "confused");
+    for t in &templates {
+        let own: Vec<&Image<bool>> = samples.iter().filter(|s| s.0 == t.marble).map(|s| &s.1).collect();
+        let mut means = vec![0.0; templates.len()];
+        let mut confused = 0;
+        for sample in &own {
+            let edges: Vec<(i32, i32)> = (0..sample.data.len()).filter(|&i| sample[i])
+                .map(|i| ((i % sample.w) as i32, (i / sample.w) as i32))
+                .collect();
+            let distance = distance_transform(sample);
+            let scores: Vec<f32> = templates.iter().map(|u| u.chamfer_score(&edges, &distance)).collect();
+            let own_score = scores[templates.iter().position(|u| u.marble == t.marble).unwrap()];
+            if templates.iter().zip(&scores).any(|(u, &s)| u.marble != t.marble && s >= own_score) { confused += 1 }
+            for (mean, s) in means.iter_mut().zip(scores) { *mean += s / own.len() as f32 }
+        }
+        let own_mean = means[templates.iter().position(|u| u.marble == t.marble).unwrap()];
+        let closest = templates.iter().zip(&means).filter(|&(u, _)| u.marble != t.marble)
+            .max_by(|a, b| a.1.partial_cmp(b.1).unwrap());
+        match closest {
+            Some((u, &score)) => println!("{:>8} {:>8} {:>8.3} {:>8} {:>8.3} {:>8}", t.marble.to_string(), own.len(),
+                                          own_mean, u.marble.to_string(), score, confused),
+            None => println!("{:>8} {:>8} {:>8.3}", t.marble.to_string(), own.len(), own_mean),
+        }
+    }
+}
+
+/// `ocr-eval [--font <dir>] <screenshot> <board file> ...`: reads each screenshot with every metric and
+/// counts the cells that differ from the board file. Then compares glyphs, colours and both fused on
+/// each kind of marble, with a colour model trained on the other screenshots.
+pub fn evaluate(args: &[String]) {
+    let (font_dir, args) = match args.first() {
+        Some(arg) if arg == "--font" && args.len() > 1 => (Some(&args[1]), &args[2..]),
+        _ => (None, args),
+    };
+    let new_reader = || {
+        let mut reader = BoardReader::new();
+        if let Some(dir) = font_dir {
+            reader.use_font_dir(dir).unwrap_or_else(|e| panic!("{}", e));
+        }
+        reader
+    };
+    let labelled = load_labelled(args, "ocr-eval [--font <dir>] <screenshot> <board file> [<screenshot> <board file> ...]");
+
+    // Occupancy evidence with whether the cell really holds a marble. It doesn't depend on the metric.
+    let mut evidence = Vec::new();
+    for &metric in METRICS.iter() {
+        let (mut misread, mut cells) = (0, 0);
+        let start = Instant::now();
+        for &(ref path, ref screenshot, ref truth) in &labelled {
+            let mut reader = new_reader();
+            reader.metric = metric;
+            match reader.read_image(screenshot) {
+                Err(e) => println!("{:?}: {}: {}", metric, path, e),
+                Ok(read) => for &((x, y), ref r) in &read.cells {
+                    if metric == Metric::Chamfer && read.free_disagreements.contains(&(x, y)) {
+                        println!("{}: {:?} read as {:?} looks {}free but the rules say otherwise", path, (x, y),
+                                 read.board.board[y][x], if r.appears_free == Some(true) { "" } else { "not " });
+                    }
+                    cells += 1;
+                    if read.board.board[y][x] != truth.board[y][x] { misread += 1 }
+                    if metric == Metric::Chamfer {
+                        evidence.push((r.occupancy.evidence, truth.board[y][x] != Marble::Empty));
+                    }
+                },
+            }
+        }
+        let elapsed = start.elapsed();
+        println!("{:?}: {} of {} cells misread ({:.1}%), {:.2}s", metric, misread, cells,
+                 100.0 * misread as f32 / std::cmp::max(cells, 1) as f32,
+                 elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 * 1e-9);
+    }
+
+    // Every threshold between neighbouring samples, counting the cells it gets wrong.
+    evidence.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    let errors = |threshold: f32| evidence.iter().filter(|&&(e, occupied)| (e > threshold) != occupied).count();
+    let best = evidence.windows(2).map(|w| (w[0].0 + w[1].0) / 2.0)
+        .min_by_key(|&t| errors(t));
+    println!("Occupancy: {} cells wrong at threshold {}", errors(OCCUPIED_THRESHOLD), OCCUPIED_THRESHOLD);
+    if let Some(t) = best {
+        println!("Occupancy: best threshold for these screenshots is {:.3}, {} cells wrong", t, errors(t));
+    }
+
+    let cells = labelled_cells(&labelled, &new_reader);
+    if cells.len() < 2 {
+        println!("Colour: needs two or more screenshots to test on one with a model from the others");
+        return;
+    }
+    let best_of = |scores: &[(Marble, f32)]| scores.iter()
+        .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+        .map_or(Marble::Empty, |s| s.0);
+    // Misreads by marble, for glyphs, colour and fused, and the number of samples.
+    let mut wrong: Vec<(Marble, [usize; 3], usize)> = MARBLE_VALUES.iter().map(|&m| (m, [0; 3], 0)).collect();
+    for i in 0..cells.len() {
+        let samples: Vec<(Marble, Vec<f32>)> = cells.iter().enumerate().filter(|&(j, _)| j != i)
+            .flat_map(|(_, c)| c.iter().map(|&(m, ref r)| (m, r.histogram.clone())))
+            .collect();
+        let model = ColourModel::train(&samples);
+        for &(truth, ref r) in &cells[i] {
+            let colour = model.scores(&r.histogram);
+            let guesses = [best_of(&r.edge_scores), best_of(&colour), best_of(&fuse(&r.edge_scores, &colour, COLOUR_WEIGHT))];
+            let row = wrong.iter_mut().find(|w| w.0 == truth).unwrap();
+            row.2 += 1;
+            for k in 0..3 {
+                if guesses[k] != truth { row.1[k] += 1 }
+            }
+        }
+    }
+    println!("{:>8} {:>8} {:>8} {:>8} {:>8}", "marble", "samples", "glyphs", "colour", "fused");
+    for &(marble, w, n) in wrong.iter().filter(|w| w.2 > 0) {
+        println!("{:>8} {:>8} {:>8} {:>8} {:>8}", marble.to_string(), n, w[0], w[1], w[2]);
+    }
+    let total = |k: usize| wrong.iter().map(|w| w.1[k]).sum::<usize>();
+    println!("{:>8} {:>8} {:>8} {:>8} {:>8}", "all", wrong.iter().map(|w| w.2).sum::<usize>(), total(0), total(1), total(2));
+}
+
+/// Per-cell accuracy `ocr-test` fails below unless told otherwise.
+const MIN_ACCURACY: f32 = 0.99;
+
+/// The board centre a ground-truth board file gives on a `# centre <x> <y>` line, in pixels.
+fn truth_centre(text: &str) -> Option<(i32, i32)> {
+    text.lines().filter_map(|l| {
+        let words: Vec<&str> = l.trim_left_matches('#').split_whitespace().collect();
+        if l.starts_with('#') && words.len() == 3 && words[0] == "centre" {
+            Some((words[1].parse().ok()?, words[2].parse().ok()?))
+        } else {
+            None
+        }
+    }).next()
+}
+
+/// `ocr-test <dir> [--min-accuracy A] [--profile <name>] [--font <dir>]`: reads every `<name>.png`
+/// in the directory that has a `<name>.txt` board file beside it, and reports per-cell accuracy, a
+/// confusion matrix, how far the board centre was from the one the board file gives and how long reads
+/// took. Each screenshot gets a reader set up by `configure` as the bot would set one up on that screen.
+/// Exits with 1 if the accuracy is below `A`, `MIN_ACCURACY` by default, or a board isn't found.
+pub fn regression(args: &[String], configure: &Fn(Option<&String>, Option<&String>, &mut Screenshot) -> Option<BoardReader>) {
+    let usage = || -> ! {
+        println!("usage: sigmar_bot ocr-test <dir> [--min-accuracy A] [--profile <name>] [--font <dir>]");
+        std::process::exit(1)
+    };
+    let dir = args.first().unwrap_or_else(|| usage());
+    let (mut min_accuracy, mut profile_name, mut font_dir) = (MIN_ACCURACY, None, None);
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--min-accuracy" => min_accuracy = rest.next().and_then(|a| a.parse().ok()).unwrap_or_else(|| usage()),
+            "--profile" => profile_name = Some(rest.next().unwrap_or_else(|| usage())),
+            "--font" => font_dir = Some(rest.next().unwrap_or_else(|| usage())),
+            _ => usage(),
+        }
+    }
+    let mut screenshots: Vec<std::path::PathBuf> = std::fs::read_dir(dir).unwrap_or_else(|e| panic!("{}: {}", dir, e))
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().map_or(false, |e| e == "png") && p.with_extension("txt").exists())
+        .collect();
+    screenshots.sort();
+    if screenshots.is_empty() {
+        println!("No screenshots with board files in {}", dir);
+        std::process::exit(1);
+    }
+
+    // Rows are the truth, columns what was read, both in `MARBLE_VALUES` order and then empty.
+    let kinds: Vec<Marble> = MARBLE_VALUES.iter().cloned().chain(Some(Marble::Empty)).collect();
+    let index = |m: Marble| kinds.iter().position(|&k| k == m).unwrap();
+    let mut confusion = [[0usize; 15]; 15];
+    let (mut not_found, mut offsets, mut times) = (0, Vec::new(), Vec::new());
+    for path in &screenshots {
+        let name = path.display().to_string();
+        let mut screenshot = Screenshot::load(&name).unwrap_or_else(|e| panic!("{}: {}", name, e));
+        let mut text = String::new();
+        File::open(path.with_extension("txt")).and_then(|mut f| f.read_to_string(&mut text))
+            .unwrap_or_else(|e| panic!("{}: {}", name, e));
+        let truth = Board::from_text(&text).unwrap_or_else(|e| panic!("{}: {}", name, e));
+        let mut reader = match configure(profile_name, font_dir, &mut screenshot) {
+            Some(reader) => reader,
+            None => std::process::exit(1),
+        };
+
+        let start = Instant::now();
+        let read = reader.read(&mut screenshot);
+        let elapsed = start.elapsed();
+        times.push(elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 * 1e-9);
+        let read = match read {
+            Ok(read) => read,
+            Err(e) => {
+                println!("{}: {}", name, e);
+                not_found += 1;
+                continue;
+            },
+        };
+        let mut wrong = 0;
+        for &(x, y) in &board_cells() {
+            let (t, r) = (truth.board[y][x], read.board.board[y][x]);
+            confusion[index(t)][index(r)] += 1;
+            if t != r { wrong += 1 }
+        }
+        let offset = truth_centre(&text).map(|(x, y)| {
+            let (dx, dy) = ((read.centre.0 - x) as f32, (read.centre.1 - y) as f32);
+            (dx * dx + dy * dy).sqrt()
+        });
+        if let Some(d) = offset { offsets.push(d) }
+        println!("{}: {} cells misread{}", name, wrong,
+                 offset.map_or(String::new(), |d| format!(", centre off by {:.1}px", d)));
+    }
+
+    print!("{:>6}", "");
+    for k in &kinds { print!("{:>5}", k.symbol()) }
+    println!();
+    for (i, k) in kinds.iter().enumerate() {
+        print!("{:>6}", k.symbol());
+        for j in 0..kinds.len() { print!("{:>5}", confusion[i][j]) }
+        println!();
+    }
+
+    let cells: usize = confusion.iter().map(|row| row.iter().sum::<usize>()).sum();
+    let right: usize = (0..kinds.len()).map(|i| confusion[i][i]).sum();
+    let accuracy = right as f32 / std::cmp::max(cells, 1) as f32;
+    println!("Accuracy: {} of {} cells ({:.2}%)", right, cells, 100.0 * accuracy);
+    if !offsets.is_empty() {
+        println!("Centre error: mean {:.1}px, worst {:.1}px", offsets.iter().sum::<f32>() / offsets.len() as f32,
+                 offsets.iter().cloned().fold(0.0, f32::max));
+    }
+    println!("Time per read: mean {:.3}s, worst {:.3}s", times.iter().sum::<f32>() / times.len() as f32,
+             times.iter().cloned().fold(0.0, f32::max));
+    if not_found > 0 || accuracy < min_accuracy {
+        println!("FAILED: {} boards not found, accuracy {:.4} against a minimum of {}", not_found, accuracy, min_accuracy);
+        std::process::exit(1);
+    }
 }
 
 fn _save_screenshot(buffer: &Vec<u8>, buffer_w: usize, buffer_h: usize) {
@@ -520,3 +2254,404 @@ fn _save_screenshot(buffer: &Vec<u8>, buffer_w: usize, buffer_h: usize) {
 fn board_pos_to_screen(x: i32, y: i32) -> (f32, f32) {
     (x as f32 + (y as f32 / 2.0), -y as f32)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+    use rand::{SeedableRng, XorShiftRng};
+    use ::designer::random_deal;
+    use ::ocr::*;
+    use ::synthetic::{render, Scene};
+
+    /// Glyph edges drawn on a dark background for the cells of a board, at the given scale. Every
+    /// third cell is left empty.
+    fn board_image(scale: f32) -> (Image<SRGB>, i32, i32) {
+        let (w, h) = (2400, 1500);
+        let mut image = Image{w, h, data: vec![SRGB{r: 40, g: 35, b: 30}; w * h]};
+        let font = scaled_font(&FONT, scale);
+        let (cx, cy) = (1203, 748);
+        for (i, &(q, r)) in GRID_OFFSETS.iter().enumerate() {
+            if i % 3 == 2 { continue }
+            let (_, ref glyph) = font[i % font.len()];
+            let x0 = cx + (q * TILE_WIDTH * scale) as i32 - glyph.w as i32 / 2;
+            let y0 = cy - (r * TILE_HEIGHT * scale) as i32 - glyph.h as i32 / 2;
+            for j in 0..glyph.data.len() {
+                if glyph[j] {
+                    let (x, y) = (x0 + (j % glyph.w) as i32, y0 + (j / glyph.w) as i32);
+                    image.data[y as usize * w + x as usize] = SRGB{r: 220, g: 210, b: 190};
+                }
+            }
+        }
+        (image, cx, cy)
+    }
+
+    #[test]
+    fn test_locate_gold() {
+        let (w, h) = (1280, 720);
+        let mut image = Image{w, h, data: (0..w * h).map(|i| {
+            let v = (i * 7919 % 97) as u8;
+            SRGB{r: v, g: v / 2 + 40, b: 120}
+        }).collect()};
+        let paste = |image: &mut Image<SRGB>, (x, y): (usize, usize)| {
+            for my in 0..3 {
+                for mx in 0..5 {
+                    image.data[(y + my - 1) * w + x + mx - 2] = GOLD_PIXEL_VALUES[my][mx];
+                }
+            }
+        };
+        paste(&mut image, (613, 301));
+        let found = |image: &Image<SRGB>, last| locate_gold(image, &GOLD_PIXEL_VALUES, last).ok().map(|f| f.0);
+        // Too small to make a gold block, so only the full search finds it.
+        assert!(coarse_gold(&image, &GOLD_PIXEL_VALUES).0 > GOLD_THRESHOLD);
+        assert_eq!(Some((613, 301)), found(&image, None));
+        assert_eq!(Some((613, 301)), found(&image, Some((40, 40))));
+
+        // The board moved a little since last time.
+        paste(&mut image, (615, 299));
+        assert_eq!(Some((615, 299)), found(&image, Some((613, 301))));
+
+        // A whole marble, which the coarse search finds by its blocks.
+        let gold_mean = GOLD_PIXEL_VALUES.iter().flat_map(|row| row.iter()).fold((0, 0, 0), |(r, g, b), p| {
+            (r + p.r as usize, g + p.g as usize, b + p.b as usize)
+        });
+        let gold_mean = SRGB{r: (gold_mean.0 / 15) as u8, g: (gold_mean.1 / 15) as u8, b: (gold_mean.2 / 15) as u8};
+        for y in 180..220 {
+            for x in 280..320 {
+                let (dx, dy) = (x as i32 - 300, y as i32 - 200);
+                if dx * dx + dy * dy < 400 { image.data[y * w + x] = gold_mean }
+            }
+        }
+        paste(&mut image, (300, 200));
+        assert_eq!((300, 200), coarse_gold(&image, &GOLD_PIXEL_VALUES).1);
+        assert_eq!(Some((300, 200)), found(&image, None));
+    }
+
+    #[test]
+    fn test_detect_scale() {
+        for &scale in &[0.75, 1.0, 1.5, 2.0] {
+            let (image, cx, cy) = board_image(scale);
+            let detected = detect_scale(&image, cx, cy, TILE_WIDTH);
+            assert!((detected - scale).abs() < 0.02, "scale {} detected as {}", scale, detected);
+        }
+    }
+
+    #[test]
+    fn test_locate_grid() {
+        for &scale in &[1.0, 1.6] {
+            let (image, cx, cy) = board_image(scale);
+            let Location { centre: (x, y), scale: detected, .. } = locate_grid(&image, (TILE_WIDTH, TILE_HEIGHT), None).unwrap();
+            assert!((detected - scale).abs() < 0.03, "scale {} detected as {}", scale, detected);
+            assert!((x - cx).abs() <= 3 && (y - cy).abs() <= 3, "centre ({}, {}) found at ({}, {})", cx, cy, x, y);
+        }
+    }
+
+    #[test]
+    fn test_no_grid_on_slivers() {
+        for &(w, h) in &[(0, 0), (1, 1), (0, 5), (5, 0), (1, 40), (40, 1)] {
+            let image = Image{w, h, data: vec![SRGB{r: 200, g: 30, b: 30}; w * h]};
+            assert!(edge_energy(&image).data.iter().all(|&e| e == 0.0));
+            assert!(locate_grid(&image, (TILE_WIDTH, TILE_HEIGHT), Some(1.0)).is_none(), "{}x{}", w, h);
+        }
+    }
+
+    #[test]
+    fn test_false_gold_is_checked_against_grid() {
+        let (mut image, cx, cy) = board_image(1.0);
+        // Texture in the background, as the game's has.
+        for (i, p) in image.data.iter_mut().enumerate() {
+            if p.r == 40 { p.g = 25 + (i * 7919 % 23) as u8 }
+        }
+        for my in 0..3 {
+            for mx in 0..5 {
+                image.data[(300 + my) * image.w + 200 + mx] = GOLD_PIXEL_VALUES[my][mx];
+            }
+        }
+        assert_eq!(Ok((202, 301)), locate_gold(&image, &GOLD_PIXEL_VALUES, None).map(|found| found.0));
+        let Location { centre: (x, y), .. } = BoardReader::new().locate(&image, (0, 0)).ok().unwrap();
+        assert!((x - cx).abs() <= 3 && (y - cy).abs() <= 3, "centre ({}, {}) found at ({}, {})", cx, cy, x, y);
+    }
+
+    #[test]
+    fn test_padded_frame() {
+        // Two rows of three pixels, each row padded to 16 bytes.
+        let mut data = vec![0xee; 32];
+        for y in 0..2 {
+            for x in 0..3 {
+                let i = y * 16 + x * 4;
+                data[i..i + 4].copy_from_slice(&[10 * x as u8, 100 + y as u8, 200, 255]);
+            }
+        }
+        let frame = Frame::new(data.clone(), 3, 2, 16, PixelFormat::Bgra).unwrap();
+        assert_eq!(vec![12, 12], frame.rows().map(|row| row.len()).collect::<Vec<_>>());
+        let image = frame.to_image();
+        assert_eq!((3, 2, 6), (image.w, image.h, image.data.len()));
+        assert_eq!(SRGB{r: 200, g: 101, b: 20}, image[1 * 3 + 2]);
+
+        let image = Frame::new(data.clone(), 3, 2, 16, PixelFormat::Rgba).unwrap().to_image();
+        assert_eq!(SRGB{r: 20, g: 101, b: 200}, image[1 * 3 + 2]);
+
+        // The last row needn't be padded, but has to be there.
+        assert!(Frame::new(data[..28].to_vec(), 3, 2, 16, PixelFormat::Bgra).is_ok());
+        assert!(Frame::new(data[..27].to_vec(), 3, 2, 16, PixelFormat::Bgra).is_err());
+        assert!(Frame::new(data.clone(), 5, 2, 16, PixelFormat::Bgra).is_err());
+
+        // Regions are clipped to the screen and keep where they were on it.
+        let part = frame.region(Some(((1, 1), (5, 2))));
+        let image = part.to_image();
+        assert_eq!(((1, 1), (2, 1), (3, 2)), (part.origin(), (image.w, image.h), part.screen_size()));
+        assert_eq!(SRGB{r: 200, g: 101, b: 20}, image[1]);
+        let corner = part.region(Some(((2, -1), (1, 3))));
+        assert_eq!(((2, 1), SRGB{r: 200, g: 101, b: 20}), (corner.origin(), corner.to_image()[0]));
+        assert!(Frame::cut(&data[..27], 3, 2, 16, PixelFormat::Bgra, None).is_err());
+    }
+
+    /// Shows an image, moved right by `shift` pixels.
+    struct Still { image: Image<SRGB>, shift: usize }
+
+    impl Screen for Still {
+        fn capture(&mut self, region: Option<Region>) -> Result<Frame, OcrError> {
+            let (w, h) = (self.image.w, self.image.h);
+            let rgba = (0..h).flat_map(|y| (0..w).map(move |x| (x, y))).flat_map(|(x, y)| {
+                let p = if x < self.shift { self.image[y * w] } else { self.image[y * w + x - self.shift] };
+                vec![p.r, p.g, p.b, 255]
+            }).collect();
+            Frame::packed(rgba, w, h, PixelFormat::Rgba).map(|frame| frame.region(region)).map_err(OcrError::Capture)
+        }
+    }
+
+    #[test]
+    fn test_read_board_region() {
+        let (image, cx, cy) = board_image(1.0);
+        let (w, h) = (image.w, image.h);
+        let mut screen = Still { image, shift: 0 };
+        let mut reader = BoardReader::new();
+        assert_eq!(None, reader.region());
+        let first = reader.read(&mut screen).unwrap();
+
+        // Only the board and a tile around it from now on, read the same.
+        let ((x, y), (region_w, region_h)) = reader.region().unwrap();
+        assert!(x > 0 && y > 0 && region_w < w / 2 && region_h < h);
+        assert!(x < cx - 6 * TILE_WIDTH as i32 && x + region_w as i32 > cx + 6 * TILE_WIDTH as i32);
+        let again = reader.read(&mut screen).unwrap();
+        assert_eq!(first.centre, again.centre);
+        assert_eq!(first.board.board, again.board.board);
+        let kept = reader.last_frame.as_ref().unwrap();
+        let image = kept.to_image();
+        assert_eq!(((x, y), (region_w, region_h)), (kept.origin(), (image.w, image.h)));
+
+        // Moved out of the region, the board is looked for all over again.
+        screen.shift = 500;
+        let moved = reader.read(&mut screen).unwrap();
+        assert!((moved.centre.0 - cx - 500).abs() <= 2 && (moved.centre.1 - cy).abs() <= 2);
+        assert_eq!(first.board.board, moved.board.board);
+        assert!(reader.region().unwrap().0 .0 > x + 400);
+    }
+
+    #[test]
+    fn test_colour_model() {
+        let red = SRGB{r: 200, g: 30, b: 30};
+        let grey = SRGB{r: 128, g: 128, b: 128};
+        let histogram = colour_histogram(&[red, red, red, grey]);
+        assert_eq!(2 * HUE_BINS + GREY_BINS, histogram.len());
+        assert!((histogram.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+        assert_eq!(0.75, histogram[HUE_BINS]);
+        assert_eq!(0.25, histogram[2 * HUE_BINS + GREY_BINS / 2]);
+        // Dimmed, the red stays in its bin.
+        assert_eq!(colour_histogram(&[red]), colour_histogram(&[SRGB{r: 140, g: 21, b: 21}]));
+
+        let model = ColourModel::train(&[(Marble::Fire, colour_histogram(&[red])), (Marble::Salt, colour_histogram(&[grey])),
+                                         (Marble::Fire, colour_histogram(&[red, grey]))]);
+        let scores = model.scores(&colour_histogram(&[red]));
+        assert_eq!(vec![(Marble::Salt, 0.0), (Marble::Fire, 0.75)], scores);
+
+        let path = env::temp_dir().join("sigmar-colours-test.txt");
+        let path = path.to_str().unwrap();
+        model.save(path).unwrap();
+        let loaded = ColourModel::load(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(scores, loaded.scores(&colour_histogram(&[red])));
+    }
+
+    #[test]
+    fn test_fuse() {
+        let edge = [(Marble::Fire, 0.9), (Marble::Water, 0.9), (Marble::Air, 0.5)];
+        assert_eq!(edge.to_vec(), fuse(&edge, &[], COLOUR_WEIGHT));
+        // Water has no colour prototype: it's fused with the mean, and Fire's better colour wins.
+        let fused = fuse(&edge, &[(Marble::Fire, 0.6), (Marble::Air, 0.2)], 0.5);
+        assert_eq!(vec![(Marble::Fire, 0.75), (Marble::Water, 0.65), (Marble::Air, 0.35)], fused);
+    }
+
+    #[test]
+    fn test_low_confidence_cells() {
+        let marble = Occupancy::from_evidence(4.0);
+        let clean = Recognition::from_scores(marble, vec![(Marble::Water, 0.25), (Marble::Fire, 0.75), (Marble::Air, 0.125)]);
+        let ambiguous = Recognition::from_scores(marble, vec![(Marble::Fire, 0.88), (Marble::Earth, 0.9)]);
+        // Barely different from an empty tile.
+        let faint = Recognition::from_scores(Occupancy::from_evidence(1.05), vec![(Marble::Salt, 0.9), (Marble::Air, 0.1)]);
+        assert_eq!((Marble::Fire, 0.5), (clean.marble(), clean.margin));
+        assert_eq!(vec![Marble::Fire, Marble::Water, Marble::Air], clean.scores.iter().map(|s| s.0).collect::<Vec<_>>());
+        assert!((ambiguous.confidence - 0.02 / 0.9).abs() < 1e-6);
+        assert!(faint.confidence > 0.8 && faint.certainty() < LOW_CONFIDENCE);
+
+        let mut board = [[Marble::Empty; 13]; 13];
+        board[6][6] = Marble::Fire;
+        board[6][7] = Marble::Earth;
+        board[7][6] = Marble::Salt;
+        let read = BoardRead::new(Board::offline(board), vec![((6, 6), clean), ((7, 6), ambiguous), ((6, 7), faint)], &[0.0; 14]);
+        assert_eq!(vec![(7, 6), (6, 7)], read.low_confidence);
+        assert!((read.confidence - 0.02 / 0.9).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_read_errors() {
+        let blank = Image{w: 800, h: 600, data: vec![SRGB{r: 40, g: 35, b: 30}; 800 * 600]};
+        match BoardReader::new().read_image(&blank) {
+            Err(OcrError::BoardNotFound { .. }) => {},
+            other => panic!("blank screen read as {:?}", other.map(|r| r.centre)),
+        }
+
+        // The left of the board cut off, as if the window hung off the screen.
+        let (image, cx, _) = board_image(1.0);
+        let left = cx as usize - 300;
+        let cut = Image{w: image.w - left, h: image.h, data: (0..image.h)
+            .flat_map(|y| image.data[y * image.w + left..(y + 1) * image.w].to_vec()).collect()};
+        match BoardReader::new().read_image(&cut) {
+            Err(OcrError::OffScreen { .. }) => {},
+            other => panic!("cut off board read as {:?}", other.map(|r| r.centre)),
+        }
+    }
+
+    #[test]
+    fn test_debug_image() {
+        let (image, cx, cy) = board_image(1.0);
+        let read = BoardReader::new().read_image(&image).unwrap();
+        let debug = debug_image(&image, &read, &FONT);
+        assert_eq!(image.w, debug.w);
+        assert!(debug.h > image.h);
+        assert_eq!(DEBUG_CENTRE, debug[read.centre.1 as usize * debug.w + read.centre.0 as usize]);
+        assert!((read.centre.0 - cx).abs() <= 3 && (read.centre.1 - cy).abs() <= 3);
+        // The top left corner of the patch of the centre cell.
+        let (x, y) = (read.centre.0 + 1 - GLYPH_WIDTH as i32 / 2, read.centre.1 - GLYPH_HEIGHT as i32 / 2);
+        assert!(debug[y as usize * debug.w + x as usize] != image[y as usize * image.w + x as usize]);
+    }
+
+    #[test]
+    fn test_truth_centre() {
+        assert_eq!(Some((960, 540)), truth_centre("# a board\n# centre 960 540\n. . .\n"));
+        assert_eq!(None, truth_centre("# centre somewhere\n"));
+    }
+
+    #[test]
+    fn test_chamfer_tolerates_shift() {
+        let font = Font::new(&FONT, 1.0);
+        for &(marble, ref glyph) in &font.glyphs {
+            let mut shifted = Image{w: glyph.w, h: glyph.h, data: vec![false; glyph.data.len()]};
+            for y in 1..glyph.h {
+                for x in 0..glyph.w - 1 {
+                    shifted.data[(y - 1) * glyph.w + x + 1] = glyph[y * glyph.w + x];
+                }
+            }
+            let edges: Vec<(i32, i32)> = (0..shifted.data.len()).filter(|&i| shifted[i])
+                .map(|i| ((i % shifted.w) as i32, (i / shifted.w) as i32))
+                .collect();
+            let distance = distance_transform(&shifted);
+            let best = font.templates.iter()
+                .max_by(|a, b| a.chamfer_score(&edges, &distance).partial_cmp(&b.chamfer_score(&edges, &distance)).unwrap())
+                .unwrap();
+            assert_eq!(marble, best.marble);
+        }
+    }
+
+    #[test]
+    fn test_profile_file() {
+        let gold = "166,147,112 ".repeat(15);
+        let text = format!("name solitaire 1440p # a comment\ntile 88 76\ngold {}\nnew-game -4.5 7\n", gold);
+        let profile = Profile::parse(&text, Path::new(".")).unwrap();
+        assert_eq!("solitaire 1440p", profile.name);
+        assert_eq!((88.0, 76.0), profile.tile);
+        assert_eq!((-4.5, 7.0), profile.new_game);
+        assert_eq!(SRGB{r: 166, g: 147, b: 112}, profile.gold[2][4]);
+
+        assert!(Profile::parse("name no gold\ntile 88 76\n", Path::new(".")).is_err());
+        assert!(Profile::parse(&format!("{}colour red\n", text), Path::new(".")).is_err());
+    }
+
+    #[test]
+    fn test_detect_profile() {
+        // The same board in a game whose gold is bluer.
+        let mut rng = XorShiftRng::from_seed([0, 1, 2, 3]);
+        let original = render(&random_deal(&mut rng), &Scene::new(), &mut rng).to_image();
+        let mut image = original.clone();
+        let mut gold = GOLD_PIXEL_VALUES;
+        for my in 0..3 {
+            for mx in 0..5 {
+                let p = GOLD_PIXEL_VALUES[my][mx];
+                gold[my][mx] = SRGB{r: p.b, g: p.g, b: p.r};
+                image.data[(539 + my) * image.w + 958 + mx] = gold[my][mx];
+            }
+        }
+        let profiles = vec![Profile::opus_magnum(), Profile { name: "blue".to_string(), gold, ..Profile::opus_magnum() }];
+        let (found, _) = detect_profile(&profiles, &image).ok().unwrap();
+        assert_eq!("blue", profiles[found].name);
+        let (found, _) = detect_profile(&profiles, &original).ok().unwrap();
+        assert_eq!("opus-magnum", profiles[found].name);
+    }
+
+    #[test]
+    fn test_glyph_size_is_checked() {
+        assert_eq!(14, FONT.len());
+        let wide = image::DynamicImage::ImageLuma8(ImageBuffer::new(GLYPH_WIDTH as u32 + 1, GLYPH_HEIGHT as u32));
+        assert!(decode_glyph("wide.png", Ok(wide)).is_err());
+        assert!(load_font_dir("no such font").is_err());
+    }
+
+    #[test]
+    fn test_train_glyphs_votes_out_jitter() {
+        let samples: Vec<(Marble, Image<bool>)> = FONT.iter().flat_map(|&(marble, ref glyph)| {
+            [(0, 0), (1, 0), (0, 0), (0, -1), (0, 0)].iter()
+                .map(|&(dx, dy)| (marble, shift_edges(glyph, dx, dy)))
+                .collect::<Vec<_>>()
+        }).collect();
+        let trained = train_glyphs(&samples);
+        assert_eq!(FONT.len(), trained.len());
+        for (&(marble, ref glyph), &(trained_marble, ref trained_glyph)) in FONT.iter().zip(&trained) {
+            assert_eq!(marble, trained_marble);
+            assert!(glyph.data == trained_glyph.data, "{:?} differs from its samples", marble);
+        }
+    }
+
+    #[test]
+    fn test_occupancy() {
+        let (image, cx, cy) = board_image(1.0);
+        let font = Font::new(&FONT, 1.0);
+        for (i, &(q, r)) in GRID_OFFSETS.iter().enumerate().take(6) {
+            let (x, y) = (cx + (q * TILE_WIDTH) as i32, cy - (r * TILE_HEIGHT) as i32);
+            let recognition = recognize_marble_at(&image, x, y, &font, Metric::Chamfer, None);
+            assert_eq!(i % 3 != 2, recognition.occupancy.occupied, "cell {}: {:?}", i, recognition.occupancy);
+        }
+    }
+
+    #[test]
+    fn test_free_disagreements() {
+        let deal = random_deal(&mut XorShiftRng::from_seed([1, 2, 3, 4]));
+        let occupancy = Occupancy::from_evidence(4.0);
+        let least_metal = deal.least_metal();
+        let mut odd_one = None;
+        let cells = board_cells().into_iter().filter(|&(x, y)| deal.board[y][x] != Marble::Empty).map(|(x, y)| {
+            let marble = deal.board[y][x];
+            let metal = (marble as usize) <= Marble::Gold as usize;
+            let mut free = deal.is_free(x, y) && (!metal || marble == least_metal);
+            // One locked element drawn bright.
+            if !free && marble == Marble::Fire && odd_one.is_none() {
+                odd_one = Some((x, y));
+                free = true;
+            }
+            let mut r = Recognition::from_scores(occupancy, vec![(marble, 1.0)]);
+            r.brightness = if free { 0.6 } else { 0.3 };
+            ((x, y), r)
+        }).collect();
+
+        let read = BoardRead::new(deal.clone(), cells, &[0.0; 14]);
+        assert_eq!(vec![odd_one.unwrap()], read.free_disagreements);
+    }
+}
@@ -0,0 +1,123 @@
+use depgraph::DependencyGraph;
+use mouse::CURSOR_SPEED;
+use sigmar::{Board, Move};
+
+/// A solution reordered for less cursor travel. Travel is measured in tile widths.
+pub struct ClickPlan {
+    /// Moves in click order, each clicked `a` first.
+    pub moves: Vec<Move>,
+    pub travel: f32,
+    pub original_travel: f32,
+}
+
+impl ClickPlan {
+    /// Estimated seconds saved over clicking the solution as it was found, at `CURSOR_SPEED`.
+    pub fn time_saved(&self) -> f32 {
+        (self.original_travel - self.travel) / CURSOR_SPEED
+    }
+}
+
+fn distance(board: &Board, from: (f32, f32), to: (f32, f32)) -> f32 {
+    let (tile_w, tile_h) = board.tile_size();
+    // Rows of a hex grid are sqrt(3)/2 tile widths apart.
+    let dx = (to.0 - from.0) / tile_w;
+    let dy = (to.1 - from.1) / tile_h * 0.866;
+    (dx * dx + dy * dy).sqrt()
+}
+
+fn clicks(board: &Board, m: Move) -> ((f32, f32), (f32, f32)) {
+    (board.pos_to_screen(m.a.x, m.a.y), board.pos_to_screen(m.b.x, m.b.y))
+}
+
+/// Cursor travel for clicking the moves in order, starting from `start`.
+pub fn travel(board: &Board, moves: &[Move], start: (f32, f32)) -> f32 {
+    let mut cursor = start;
+    let mut total = 0.0;
+    for &m in moves {
+        let (a, b) = clicks(board, m);
+        total += distance(board, cursor, a) + distance(board, a, b);
+        cursor = b;
+    }
+    total
+}
+
+/// Clicks the marbles of a move in whichever order is closer to the cursor.
+fn oriented(board: &Board, m: Move, cursor: (f32, f32)) -> Move {
+    let (a, b) = clicks(board, m);
+    if distance(board, cursor, b) < distance(board, cursor, a) { Move { a: m.b, b: m.a } } else { m }
+}
+
+/// Picks the order of the solution's moves, among those its dependency graph allows, with the least
+/// cursor travel from `start`. Greedily takes the nearest ready move, then swaps neighbouring moves
+/// while that helps.
+pub fn plan_clicks(board: &Board, solution: &[Move], start: (f32, f32)) -> ClickPlan {
+    let graph = DependencyGraph::new(solution);
+    let original_travel = travel(board, solution, start);
+
+    let mut done = vec![false; graph.len()];
+    let mut order = Vec::with_capacity(graph.len());
+    let mut cursor = start;
+    while order.len() < graph.len() {
+        let next = graph.ready(&done).into_iter().min_by(|&i, &j| {
+            let cost = |k: usize| {
+                let (a, b) = clicks(board, oriented(board, graph.moves[k], cursor));
+                distance(board, cursor, a) + distance(board, a, b)
+            };
+            cost(i).partial_cmp(&cost(j)).unwrap()
+        }).unwrap();
+        let m = oriented(board, graph.moves[next], cursor);
+        done[next] = true;
+        order.push(next);
+        cursor = board.pos_to_screen(m.b.x, m.b.y);
+    }
+
+    let orient_all = |order: &[usize]| -> Vec<Move> {
+        let mut cursor = start;
+        order.iter().map(|&i| {
+            let m = oriented(board, graph.moves[i], cursor);
+            cursor = board.pos_to_screen(m.b.x, m.b.y);
+            m
+        }).collect()
+    };
+
+    let mut best = travel(board, &orient_all(&order), start);
+    let mut improved = true;
+    while improved {
+        improved = false;
+        for i in 0..order.len().saturating_sub(1) {
+            order.swap(i, i + 1);
+            let candidate = travel(board, &orient_all(&order), start);
+            if candidate + 1e-4 < best && graph.is_valid_order(&order) {
+                best = candidate;
+                improved = true;
+            } else {
+                order.swap(i, i + 1);
+            }
+        }
+    }
+
+    if best < original_travel {
+        ClickPlan { moves: orient_all(&order), travel: best, original_travel }
+    } else {
+        ClickPlan { moves: solution.to_vec(), travel: original_travel, original_travel }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::designer::solved_deal;
+    use ::planner::*;
+    use ::sigmar::NEW_GAME_OFFSET;
+    use ::solver::is_solution;
+
+    #[test]
+    fn test_plan_is_valid_and_shorter() {
+        let (board, solution) = solved_deal(1);
+        let start = board.tiles_from_centre(NEW_GAME_OFFSET);
+        let plan = plan_clicks(&board, &solution, start);
+        assert!(is_solution(&board, &plan.moves));
+        assert!(plan.travel <= plan.original_travel);
+        assert!((travel(&board, &plan.moves, start) - plan.travel).abs() < 1e-3);
+        assert!(plan.time_saved() >= 0.0);
+    }
+}
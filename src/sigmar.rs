@@ -3,9 +3,12 @@ use std::cmp::{min, max};
 use std::fmt;
 use itertools::Itertools;
 use rand::{thread_rng, Rng};
-use std::collections::HashSet;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+use analysis::{Analysis, Difficulty, DIFFICULTY_MAX_POSITIONS, DIFFICULTY_SOLUTION_CAP};
+use solver::{Dfs, Solver};
+use tablebase::Tablebase;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Marble {
     Lead = 0,
     Tin = 1,
@@ -28,6 +31,43 @@ pub const MARBLE_VALUES: [Marble; 14] = [Marble::Salt, Marble::Air, Marble::Fire
     Marble::Lead, Marble::Tin, Marble::Iron, Marble::Copper, Marble::Silver, Marble::Gold, Marble::Mercury,
     Marble::Vitae, Marble::Mors];
 
+/// The new game button in Opus Magnum, in tiles right and down from the board centre.
+pub const NEW_GAME_OFFSET: (f32, f32) = (-5.0, 6.5);
+
+/// Number of marbles of each kind in a fresh deal.
+pub const DEAL_COUNTS: [(Marble, usize); 14] = [(Marble::Salt, 4), (Marble::Air, 8), (Marble::Fire, 8),
+    (Marble::Water, 8), (Marble::Earth, 8), (Marble::Lead, 1), (Marble::Tin, 1), (Marble::Iron, 1),
+    (Marble::Copper, 1), (Marble::Silver, 1), (Marble::Gold, 1), (Marble::Mercury, 5),
+    (Marble::Vitae, 4), (Marble::Mors, 4)];
+
+impl Marble {
+    /// Single character used for the marble in board files.
+    pub fn symbol(&self) -> char {
+        match *self {
+            Marble::Lead => 'L',
+            Marble::Tin => 'T',
+            Marble::Iron => 'I',
+            Marble::Copper => 'C',
+            Marble::Silver => 'S',
+            Marble::Gold => 'G',
+            Marble::Mercury => 'Q',
+            Marble::Air => 'a',
+            Marble::Fire => 'f',
+            Marble::Water => 'w',
+            Marble::Earth => 'e',
+            Marble::Vitae => 'v',
+            Marble::Mors => 'm',
+            Marble::Salt => 's',
+            Marble::Empty => '.',
+        }
+    }
+
+    pub fn from_symbol(c: char) -> Option<Marble> {
+        if c == '.' { return Some(Marble::Empty) }
+        MARBLE_VALUES.iter().cloned().find(|m| m.symbol() == c)
+    }
+}
+
 impl fmt::Display for Marble {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?}", self)
@@ -60,6 +100,54 @@ lazy_static! {
 }
 
 impl Board {
+    /// Board that wasn't read from the screen. Geometry is that of a centred board on a 1080p display.
+    pub fn offline(board: [[Marble; 13]; 13]) -> Board {
+        Board::new(board, 0.5, 0.5, 66.0 / 1920.0, 57.0 / 1080.0)
+    }
+
+    /// Parses a board file: 11 rows of marble symbols, top row first. Blank lines and lines starting
+    /// with `#` are ignored.
+    pub fn from_text(text: &str) -> Result<Board, String> {
+        let lines: Vec<&str> = text.lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .collect();
+        if lines.len() != 11 {
+            return Err(format!("expected 11 rows, found {}", lines.len()));
+        }
+
+        let mut board = [[Marble::Empty; 13]; 13];
+        for (i, r) in board_rows().iter().enumerate() {
+            let line = lines[10 - i];
+            let symbols: Vec<char> = line.chars().filter(|c| !c.is_whitespace()).collect();
+            let expected = (r.x_max - r.x_min + 1) as usize;
+            if symbols.len() != expected {
+                return Err(format!("row '{}' has {} cells, expected {}", line, symbols.len(), expected));
+            }
+            for (j, &c) in symbols.iter().enumerate() {
+                board[i + 1][r.x_min as usize + j + 1] = match Marble::from_symbol(c) {
+                    Some(marble) => marble,
+                    None => return Err(format!("unknown marble symbol '{}'", c)),
+                };
+            }
+        }
+        Ok(Board::offline(board))
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for (i, r) in board_rows().iter().enumerate().rev() {
+            let cells = (r.x_max - r.x_min + 1) as usize;
+            text.extend(std::iter::repeat(' ').take(11 - cells));
+            let row: Vec<String> = (r.x_min..r.x_max + 1)
+                .map(|x| self.board[i + 1][x as usize + 1].symbol().to_string())
+                .collect();
+            text.push_str(&row.join(" "));
+            text.push('\n');
+        }
+        text
+    }
+
     pub fn new(board: [[Marble; 13]; 13], middle_x: f32, middle_y: f32, tile_w: f32, tile_h: f32) -> Board {
         let hash: u64 = 0;
         let mut ret = Board {
@@ -80,7 +168,15 @@ impl Board {
         }
     }
 
-    fn is_free(&self, c_x: usize, c_y: usize) -> bool {
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    pub fn marble_count(&self) -> usize {
+        board_cells().iter().filter(|&&(x, y)| self.board[y][x] != Marble::Empty).count()
+    }
+
+    pub fn is_free(&self, c_x: usize, c_y: usize) -> bool {
         let (s_x, s_y) = (c_x - 1, c_y - 1);
         if self.board[c_y][c_x] == Marble::Empty { return false }
         let is_neighbour_empty = |m_x: usize, m_y: usize| self.board[s_y + m_y][s_x + m_x] == Marble::Empty;
@@ -206,9 +302,18 @@ impl Board {
 
 
     pub fn solve(&self) -> Option<Vec<Move>> {
-        let mut board = self.clone();
-        let mut visited: HashSet<u64> = HashSet::with_capacity(60_000);
-        go_solve(&mut board, &mut visited, 1)
+        Dfs::new(None).solve(self)
+    }
+
+    /// Like `solve`, but endgames are looked up from the tablebase instead of searched.
+    pub fn solve_with(&self, tablebase: &Tablebase) -> Option<Vec<Move>> {
+        Dfs::new(Some(tablebase)).solve(self)
+    }
+
+    /// Rates the board by exploring its positions, see `Difficulty`.
+    pub fn difficulty(&self) -> Difficulty {
+        let stats = Analysis::new(DIFFICULTY_SOLUTION_CAP, DIFFICULTY_MAX_POSITIONS).run(self);
+        Difficulty::from_stats(&stats)
     }
 
     pub fn pos_to_screen(&self, x: usize, y: usize) -> (f32, f32) {
@@ -217,10 +322,15 @@ impl Board {
         (self.middle_x + offset_x * self.tile_w, self.middle_y -  offset_y * self.tile_h)
     }
 
-    pub fn new_game_pos(&self) -> (f32, f32) {
-        let offset_x = self.tile_w as f32 * -5.0;
-        let offset_y = self.tile_h as f32 * 6.5;
-        (self.middle_x + offset_x, self.middle_y + offset_y)
+    /// Width and height of a tile, as fractions of the screen like `pos_to_screen`.
+    pub fn tile_size(&self) -> (f32, f32) {
+        (self.tile_w, self.tile_h)
+    }
+
+    /// The point the given number of tiles right and down from the board centre, as a fraction of the
+    /// screen like `pos_to_screen`.
+    pub fn tiles_from_centre(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        (self.middle_x + x * self.tile_w, self.middle_y + y * self.tile_h)
     }
     
     pub fn remove_marble(&mut self, pos: MarblePos) {
@@ -250,39 +360,22 @@ impl Board {
     }
 }
 
-const TOTAL_MOVES: usize = 28;
-
-fn go_solve(board: &mut Board, visited: &mut HashSet<u64>, depth: usize) -> Option<Vec<Move>> {
-
-    if visited.contains(&board.hash) { return None }
-    let legal = board.legal_moves();
-
-    for m in legal {
-        board.make_move(m);
-
-        if depth == TOTAL_MOVES {
-            let v = vec![m; TOTAL_MOVES];
-            return Some(v);
-        }
-
-        if let Some(mut vec) = go_solve(board, visited, depth + 1) {
-            vec[depth - 1] = m;
-            return Some(vec)
-        }
-        visited.insert(board.hash);
+pub const TOTAL_MOVES: usize = 28;
 
-        board.reverse_move(m);
-    }
-    None
-}
-
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub struct Move {
     pub a: MarblePos,
     pub b: MarblePos,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+impl Move {
+    /// Salt spent on an element rather than on another salt.
+    pub fn uses_salt_on_element(&self) -> bool {
+        (self.a.marble == Marble::Salt) != (self.b.marble == Marble::Salt)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub struct MarblePos {
     pub x: usize,
     pub y: usize,
@@ -311,6 +404,20 @@ pub fn board_rows() -> Vec<RowDesc> {
     a.collect()
 }
 
+/// Offsets of the six neighbours of a cell in `Board::board`.
+pub const NEIGHBOURS: [(i32, i32); 6] = [(1, 0), (1, -1), (0, -1), (-1, 0), (-1, 1), (0, 1)];
+
+/// Indices into `Board::board` of all 91 cells, as (x, y).
+pub fn board_cells() -> Vec<(usize, usize)> {
+    let mut cells = Vec::with_capacity(91);
+    for (i, r) in board_rows().iter().enumerate() {
+        for x in r.x_min..r.x_max + 1 {
+            cells.push((x as usize + 1, i + 1));
+        }
+    }
+    cells
+}
+
 #[cfg(test)]
 mod tests {
     use ::sigmar::*;
@@ -322,4 +429,32 @@ mod tests {
         assert_eq!(RowDesc{x_min: 0, x_max: 10},  rows[5]);
         assert_eq!(RowDesc{x_min: 0, x_max: 5},  *rows.last().unwrap());
     }
+
+    #[test]
+    fn test_board_text_roundtrip() {
+        let mut cells = [[Marble::Empty; 13]; 13];
+        cells[6][6] = Marble::Gold;
+        cells[1][6] = Marble::Salt;
+        cells[11][1] = Marble::Mors;
+        let board = Board::offline(cells);
+        let text = board.to_text();
+        assert!(text.starts_with("     m . . . . ."));
+        let parsed = Board::from_text(&text).unwrap();
+        assert_eq!(board.board, parsed.board);
+        assert_eq!(board.hash(), parsed.hash());
+        assert!(Board::from_text("G").is_err());
+    }
+
+    #[test]
+    fn test_difficulty_of_trivial_board() {
+        let mut cells = [[Marble::Empty; 13]; 13];
+        cells[6][6] = Marble::Gold;
+        cells[6][7] = Marble::Fire;
+        cells[6][8] = Marble::Fire;
+        let d = Board::offline(cells).difficulty();
+        assert!(d.stats.complete);
+        assert_eq!(1, d.solutions);
+        assert_eq!(None, d.first_trap);
+        assert_eq!(0.0, d.first_move_dead_ends);
+    }
 }
\ No newline at end of file
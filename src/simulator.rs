@@ -0,0 +1,373 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use designer::random_deal;
+use mouse::Mouse;
+use ocr::{cell_centres, Frame, OcrError, Region, Screen, TILE_HEIGHT, TILE_WIDTH};
+use sigmar::{Board, Marble, MarblePos, Move, NEW_GAME_OFFSET};
+use synthetic::{render, Scene};
+
+/// Time from clicking new game until the deal is on the board, in ms.
+const DEAL_TIME: u64 = 2000;
+/// Time a click takes, in ms, as `mouse::click_at` does.
+const CLICK_TIME: u64 = 130;
+/// Bytes the rows of frames start at multiples of.
+const ROW_ALIGN: usize = 256;
+
+/// Ways the simulated game can misbehave.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Faults {
+    /// Chance that a click is lost.
+    pub dropped_clicks: f32,
+    /// Time before a click takes effect, in ms.
+    pub lag: u64,
+}
+
+/// The six neighbours of a cell in `Board::board`, going round it.
+const AROUND: [(i32, i32); 6] = [(0, -1), (1, -1), (1, 0), (0, 1), (-1, 1), (-1, 0)];
+/// The metals in the order they have to be taken.
+const METALS: [Marble; 6] = [Marble::Lead, Marble::Tin, Marble::Iron, Marble::Copper, Marble::Silver, Marble::Gold];
+
+/// Whether a marble is free: three neighbours in a row round it are empty or off the board.
+fn free(board: &[[Marble; 13]; 13], (x, y): (usize, usize)) -> bool {
+    if board[y][x] == Marble::Empty { return false }
+    let empty = |i: usize| {
+        let (nx, ny) = (x as i32 + AROUND[i % 6].0, y as i32 + AROUND[i % 6].1);
+        nx < 0 || ny < 0 || nx >= 13 || ny >= 13 || board[ny as usize][nx as usize] == Marble::Empty
+    };
+    (0..6).any(|i| empty(i) && empty(i + 1) && empty(i + 2))
+}
+
+/// Whether the marbles on two cells can be taken together, or the one alone if it's the same cell.
+/// Written from the game's rules rather than with `Board`, so the two check each other.
+fn takes(board: &[[Marble; 13]; 13], a: (usize, usize), b: (usize, usize)) -> bool {
+    if !free(board, a) || !free(board, b) { return false }
+    let next_metal = METALS.iter().cloned().find(|metal| board.iter().any(|row| row.contains(metal)));
+    let element = |m: Marble| [Marble::Air, Marble::Fire, Marble::Water, Marble::Earth].contains(&m);
+    let (ma, mb) = (board[a.1][a.0], board[b.1][b.0]);
+    if a == b { return ma == Marble::Gold && next_metal == Some(Marble::Gold) }
+    match (ma, mb) {
+        (Marble::Salt, Marble::Salt) | (Marble::Vitae, Marble::Mors) | (Marble::Mors, Marble::Vitae) => true,
+        (Marble::Salt, m) | (m, Marble::Salt) => element(m),
+        (Marble::Mercury, m) | (m, Marble::Mercury) => m != Marble::Gold && next_metal == Some(m),
+        (m, n) => m == n && element(m),
+    }
+}
+
+/// Every cell of `Board::board`, on the board or not.
+fn all_cells() -> Vec<(usize, usize)> {
+    (0..13).flat_map(|y| (0..13).map(move |x| (x, y))).collect()
+}
+
+/// Sigmar's Garden without the game: keeps the board, draws it with `synthetic::render` and plays the
+/// clicks it gets by the game's rules. Time only passes with clicks and waits. Clicks are judged by
+/// `takes`, not by the bot's `Board::legal_moves`.
+struct Game {
+    scene: Scene,
+    faults: Faults,
+    rng: XorShiftRng,
+    board: Board,
+    selected: Option<(usize, usize)>,
+    /// Clicks that haven't taken effect yet, with when they will.
+    pending: Vec<(u64, (f32, f32))>,
+    /// When the next deal appears, if one is on its way.
+    deal_at: Option<u64>,
+    time: u64,
+    cursor: (f32, f32),
+    deals: usize,
+    wins: usize,
+    /// Whether the board was cleared and the banner is up, until new game is clicked.
+    won: bool,
+}
+
+impl Game {
+    /// Where the scene's board is, for `Board::pos_to_screen` and `Board::tiles_from_centre`.
+    fn layout(&self, board: [[Marble; 13]; 13]) -> Board {
+        let (w, h) = (self.scene.width as f32, self.scene.height as f32);
+        Board::new(board, self.scene.centre.0 as f32 / w, self.scene.centre.1 as f32 / h,
+                   TILE_WIDTH * self.scene.scale / w, TILE_HEIGHT * self.scene.scale / h)
+    }
+
+    fn deal(&mut self) {
+        let deal = random_deal(&mut self.rng);
+        self.board = self.layout(deal.board);
+        self.selected = None;
+        self.deals += 1;
+    }
+
+    /// Plays the clicks that are due by now.
+    fn advance(&mut self, ms: u64) {
+        self.time += ms;
+        let time = self.time;
+        let (due, pending): (Vec<_>, Vec<_>) = self.pending.drain(..).partition(|&(at, _)| at <= time);
+        self.pending = pending;
+        for (at, pos) in due {
+            self.click(at, pos);
+        }
+        if self.deal_at.map_or(false, |at| at <= time) {
+            self.deal_at = None;
+            self.deal();
+        }
+    }
+
+    fn click(&mut self, at: u64, (x, y): (f32, f32)) {
+        let (bx, by) = self.board.tiles_from_centre(NEW_GAME_OFFSET);
+        let (tile_w, tile_h) = self.board.tile_size();
+        if ((x - bx) / tile_w).abs() < 1.0 && ((y - by) / tile_h).abs() < 0.5 {
+            self.board = self.layout([[Marble::Empty; 13]; 13]);
+            self.selected = None;
+            self.won = false;
+            self.deal_at = Some(at + DEAL_TIME);
+            return;
+        }
+        // The banner is in the way of the board.
+        if self.won { return }
+
+        let (px, py) = ((x * self.scene.width as f32) as i32, (y * self.scene.height as f32) as i32);
+        let (tile_w, tile_h) = (TILE_WIDTH * self.scene.scale, TILE_HEIGHT * self.scene.scale);
+        let hit = cell_centres(self.scene.centre, (tile_w, tile_h)).into_iter().find(|&(_, (cx, cy))| {
+            let (dx, dy) = ((px - cx) as f32, (py - cy) as f32);
+            dx * dx + dy * dy < 0.42 * 0.42 * tile_w * tile_w
+        });
+        let (x, y) = match hit {
+            Some((cell, _)) => cell,
+            None => return,
+        };
+        let board = self.board.board;
+        let pos = MarblePos { x, y, marble: board[y][x] };
+        if !all_cells().into_iter().any(|cell| takes(&board, (x, y), cell)) { return }
+
+        if takes(&board, (x, y), (x, y)) {
+            self.board.make_move(Move { a: pos, b: pos });
+            self.selected = None;
+        } else {
+            match self.selected {
+                Some(cell) if cell == (x, y) => self.selected = None,
+                Some((sx, sy)) if takes(&board, (sx, sy), (x, y)) => {
+                    let a = MarblePos { x: sx, y: sy, marble: board[sy][sx] };
+                    self.board.make_move(Move { a, b: pos });
+                    self.selected = None;
+                },
+                _ => self.selected = Some((x, y)),
+            }
+        }
+        if self.board.marble_count() == 0 {
+            self.wins += 1;
+            self.won = true;
+        }
+    }
+
+    fn frame(&mut self) -> Frame {
+        let (w, h) = (self.scene.width, self.scene.height);
+        let scene = Scene {
+            cursor: Some(((self.cursor.0 * w as f32) as i32, (self.cursor.1 * h as f32) as i32)),
+            won: self.won,
+            ..self.scene.clone()
+        };
+        let frame = render(&self.board, &scene, &mut self.rng);
+        // Rows padded the way capture backends align them.
+        let stride = (w * 4 + ROW_ALIGN - 1) / ROW_ALIGN * ROW_ALIGN;
+        let mut data = vec![0; stride * h];
+        for (y, row) in frame.rows().enumerate() {
+            data[y * stride..y * stride + row.len()].copy_from_slice(row);
+        }
+        Frame::new(data, w, h, stride, frame.format()).expect("Padded frame doesn't fit its size.")
+    }
+}
+
+/// A simulated game, with a `Screen` and a `Mouse` on it for `play_games`.
+pub struct Simulator {
+    game: Rc<RefCell<Game>>,
+}
+
+impl Simulator {
+    /// Starts with a fresh deal. The same seed plays out the same way.
+    pub fn new(scene: Scene, faults: Faults, seed: u32) -> Simulator {
+        let mut game = Game {
+            scene, faults, rng: XorShiftRng::from_seed([seed, 1, 2, 3]),
+            board: Board::offline([[Marble::Empty; 13]; 13]), selected: None, pending: Vec::new(),
+            deal_at: None, time: 0, cursor: (0.0, 0.0), deals: 0, wins: 0, won: false,
+        };
+        game.deal();
+        Simulator { game: Rc::new(RefCell::new(game)) }
+    }
+
+    pub fn screen(&self) -> SimulatedScreen {
+        SimulatedScreen { game: self.game.clone() }
+    }
+
+    pub fn mouse(&self) -> SimulatedMouse {
+        SimulatedMouse { game: self.game.clone() }
+    }
+
+    /// Boards dealt, including the first.
+    pub fn deals(&self) -> usize {
+        self.game.borrow().deals
+    }
+
+    /// Boards cleared.
+    pub fn wins(&self) -> usize {
+        self.game.borrow().wins
+    }
+}
+
+pub struct SimulatedScreen {
+    game: Rc<RefCell<Game>>,
+}
+
+impl Screen for SimulatedScreen {
+    fn capture(&mut self, region: Option<Region>) -> Result<Frame, OcrError> {
+        let mut game = self.game.borrow_mut();
+        game.advance(0);
+        Ok(game.frame().region(region))
+    }
+}
+
+pub struct SimulatedMouse {
+    game: Rc<RefCell<Game>>,
+}
+
+impl Mouse for SimulatedMouse {
+    fn move_cursor(&mut self, x: f32, y: f32) {
+        self.game.borrow_mut().cursor = (x, y);
+    }
+
+    fn click_at(&mut self, x: f32, y: f32) {
+        let mut game = self.game.borrow_mut();
+        game.cursor = (x, y);
+        let dropped = game.rng.gen::<f32>() < game.faults.dropped_clicks;
+        if !dropped {
+            let at = game.time + game.faults.lag;
+            game.pending.push((at, (x, y)));
+        }
+        game.advance(CLICK_TIME);
+    }
+
+    fn wait(&mut self, ms: u64) {
+        self.game.borrow_mut().advance(ms);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::correction::correct;
+    use ::designer::solved_deal;
+    use ::ocr::BoardReader;
+    use ::play_games;
+    use ::simulator::*;
+
+    fn play(faults: Faults, games: usize) -> (Simulator, usize) {
+        let scene = Scene { width: 1000, height: 820, centre: (500, 400), ..Scene::new() };
+        let simulator = Simulator::new(scene, faults, 2);
+        let mut reader = BoardReader::new();
+        let solved = play_games(&mut reader, None, &mut simulator.screen(), &mut simulator.mouse(), games, &|_, _, _| ());
+        (simulator, solved)
+    }
+
+    /// Pairs of cells that can be taken together by `Board::legal_moves` and by `takes`, sorted.
+    fn pairs(board: &Board) -> (Vec<((usize, usize), (usize, usize))>, Vec<((usize, usize), (usize, usize))>) {
+        let mut legal: Vec<_> = board.legal_moves().into_iter().map(|m| {
+            let (a, b) = ((m.a.x, m.a.y), (m.b.x, m.b.y));
+            if a <= b { (a, b) } else { (b, a) }
+        }).collect();
+        legal.sort();
+        let cells = all_cells();
+        let mut taken = Vec::new();
+        for &a in &cells {
+            for &b in cells.iter().filter(|&&b| a <= b) {
+                if takes(&board.board, a, b) { taken.push((a, b)) }
+            }
+        }
+        taken.sort();
+        (legal, taken)
+    }
+
+    #[test]
+    fn test_rules_agree_with_legal_moves() {
+        // Down solutions, to get to every metal, and down random moves until they run out.
+        for seed in 1..4 {
+            let (mut board, solution) = solved_deal(seed);
+            for m in solution {
+                let (legal, taken) = pairs(&board);
+                assert_eq!(legal, taken, "{}", board.to_text());
+                board.make_move(m);
+            }
+        }
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        for _ in 0..20 {
+            let mut board = random_deal(&mut rng);
+            loop {
+                let (legal, taken) = pairs(&board);
+                assert_eq!(legal, taken, "{}", board.to_text());
+                let moves = board.legal_moves();
+                if moves.is_empty() { break }
+                board.make_move(moves[rng.gen_range(0, moves.len())]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bot_clears_simulated_games() {
+        let (simulator, solved) = play(Faults::default(), 2);
+        assert_eq!((2, 2, 3), (solved, simulator.wins(), simulator.deals()));
+    }
+
+    #[test]
+    fn test_banner_stays_up_until_new_game() {
+        let scene = Scene { width: 1000, height: 820, centre: (500, 400), ..Scene::new() };
+        let simulator = Simulator::new(scene, Faults::default(), 2);
+        let (mut screen, mut mouse) = (simulator.screen(), simulator.mouse());
+        let mut board = [[Marble::Empty; 13]; 13];
+        board[6][6] = Marble::Gold;
+        let layout = simulator.game.borrow().layout(board);
+        simulator.game.borrow_mut().board = layout.clone();
+
+        let (x, y) = layout.pos_to_screen(6, 6);
+        mouse.click_at(x, y);
+        assert_eq!(1, simulator.wins());
+        // Whatever the banner reads as, it isn't a board to play.
+        let mut reader = BoardReader::new();
+        if let Ok(read) = reader.read(&mut screen) {
+            let correction = correct(&read, true);
+            assert!(correction.map_or(true, |c| !c.unconfirmed.is_empty()), "played {:?}", read.board.board);
+        }
+
+        // Clicks on the board do nothing while the banner is up.
+        mouse.click_at(x, y);
+        assert_eq!(1, simulator.wins());
+
+        let (new_x, new_y) = reader.profile().new_game_pos(&layout);
+        mouse.click_at(new_x, new_y);
+        mouse.wait(DEAL_TIME);
+        let read = reader.read(&mut screen).unwrap();
+        assert_eq!((2, 55), (simulator.deals(), read.board.marble_count()));
+    }
+
+    #[test]
+    fn test_reads_before_the_deal_dont_use_up_games() {
+        let scene = Scene { width: 1000, height: 820, centre: (500, 400), ..Scene::new() };
+        let simulator = Simulator::new(scene, Faults::default(), 2);
+        let mut reader = BoardReader::new();
+        let board = simulator.game.borrow().board.clone();
+        let (new_x, new_y) = reader.profile().new_game_pos(&board);
+        // The board stays empty until the new deal, and the reads until then fail.
+        simulator.mouse().click_at(new_x, new_y);
+        let solved = play_games(&mut reader, None, &mut simulator.screen(), &mut simulator.mouse(), 1, &|_, _, _| ());
+        assert_eq!((1, 1, 3), (solved, simulator.wins(), simulator.deals()));
+    }
+
+    #[test]
+    fn test_bot_copes_with_lag() {
+        let (simulator, solved) = play(Faults { lag: 300, ..Faults::default() }, 2);
+        assert_eq!((2, 2, 3), (solved, simulator.wins(), simulator.deals()));
+    }
+
+    #[test]
+    fn test_bot_carries_on_after_dropped_clicks() {
+        // A lost click leaves marbles behind, but the next game still starts and gets read.
+        let (simulator, solved) = play(Faults { dropped_clicks: 0.05, ..Faults::default() }, 2);
+        assert_eq!((2, 3), (solved, simulator.deals()));
+        assert!(simulator.wins() < 2);
+    }
+}
@@ -0,0 +1,417 @@
+use std;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::Read;
+use std::time::Instant;
+use rand::{thread_rng, Rng, ThreadRng};
+
+use designer::random_deal;
+use sigmar::{board_cells, Board, Marble, Move, TOTAL_MOVES};
+use tablebase::{Tablebase, Verdict};
+
+/// A search strategy for finding a solution to a board.
+pub trait Solver {
+    fn name(&self) -> &'static str;
+    fn solve(&mut self, board: &Board) -> Option<Vec<Move>>;
+    /// Positions expanded by the last `solve`.
+    fn nodes(&self) -> usize;
+}
+
+/// Depth first search in the order `legal_moves` gives, remembering positions that failed.
+pub struct Dfs<'a> {
+    tablebase: Option<&'a Tablebase>,
+    /// Gives up after expanding this many positions, unlimited by default.
+    pub max_nodes: usize,
+    nodes: usize,
+    total_moves: usize,
+}
+
+/// Moves it takes to clear the board: gold goes alone, everything else in pairs.
+fn moves_to_clear(board: &Board) -> usize {
+    let gold = board_cells().iter().filter(|&&(x, y)| board.board[y][x] == Marble::Gold).count();
+    (board.marble_count() - gold + 1) / 2 + gold
+}
+
+impl<'a> Dfs<'a> {
+    pub fn new(tablebase: Option<&'a Tablebase>) -> Dfs<'a> {
+        Dfs { tablebase, max_nodes: usize::max_value(), nodes: 0, total_moves: TOTAL_MOVES }
+    }
+
+    fn go_solve(&mut self, board: &mut Board, visited: &mut HashSet<u64>, depth: usize) -> Option<Vec<Move>> {
+
+        if visited.contains(&board.hash()) || self.nodes >= self.max_nodes { return None }
+        self.nodes += 1;
+
+        if let Some(tb) = self.tablebase {
+            if board.marble_count() <= tb.max_marbles {
+                match tb.lookup(board) {
+                    Some(Verdict::Unsolvable) => return None,
+                    Some(Verdict::Solvable(..)) => if let Some(rest) = tb.solve(board) {
+                        // Leave room for the moves that led here, the callers fill them in.
+                        let mut v = vec![rest[0]; depth - 1];
+                        v.extend(rest);
+                        return Some(v);
+                    },
+                    None => {},
+                }
+            }
+        }
+
+        let legal = board.legal_moves();
+
+        for m in legal {
+            board.make_move(m);
+
+            if depth == self.total_moves {
+                let v = vec![m; self.total_moves];
+                return Some(v);
+            }
+
+            if let Some(mut vec) = self.go_solve(board, visited, depth + 1) {
+                vec[depth - 1] = m;
+                return Some(vec)
+            }
+            visited.insert(board.hash());
+
+            board.reverse_move(m);
+        }
+        None
+    }
+}
+
+impl<'a> Solver for Dfs<'a> {
+    fn name(&self) -> &'static str { "dfs" }
+
+    fn solve(&mut self, board: &Board) -> Option<Vec<Move>> {
+        self.nodes = 0;
+        self.total_moves = moves_to_clear(board);
+        if self.total_moves == 0 { return Some(Vec::new()) }
+        let mut board = board.clone();
+        let mut visited: HashSet<u64> = HashSet::with_capacity(60_000);
+        self.go_solve(&mut board, &mut visited, 1)
+    }
+
+    fn nodes(&self) -> usize { self.nodes }
+}
+
+/// Whether the marble counts alone rule out a solution: every vitae needs a mors, every metal but
+/// gold a mercury, and elements left over in odd numbers need salt.
+pub fn hopeless(board: &Board) -> bool {
+    let mut counts = [0i32; 14];
+    for (x, y) in board_cells() {
+        let marble = board.board[y][x];
+        if marble != Marble::Empty {
+            counts[marble as usize] += 1;
+        }
+    }
+    let count = |m: Marble| counts[m as usize];
+
+    let metals: i32 = [Marble::Lead, Marble::Tin, Marble::Iron, Marble::Copper, Marble::Silver].iter()
+        .map(|&m| count(m)).sum();
+    let odd_elements = [Marble::Air, Marble::Fire, Marble::Water, Marble::Earth].iter()
+        .filter(|&&m| count(m) % 2 == 1).count() as i32;
+    let salt = count(Marble::Salt);
+
+    count(Marble::Vitae) != count(Marble::Mors)
+        || metals != count(Marble::Mercury)
+        || odd_elements > salt
+        || (salt - odd_elements) % 2 == 1
+}
+
+/// Legal moves, most promising first: moves that don't spend salt on an element, then moves that
+/// leave the most moves open.
+fn ordered_moves(board: &mut Board) -> Vec<Move> {
+    let mut scored: Vec<(i32, Move)> = board.legal_moves().into_iter().map(|m| {
+        board.make_move(m);
+        let mobility = board.legal_moves().len() as i32;
+        board.reverse_move(m);
+        let salt_penalty = if m.uses_salt_on_element() { 100 } else { 0 };
+        (mobility - salt_penalty, m)
+    }).collect();
+    scored.sort_by(|a, b| b.0.cmp(&a.0));
+    scored.into_iter().map(|(_, m)| m).collect()
+}
+
+/// Iterative deepening over discrepancies. Every solution is exactly as long, so instead of depth
+/// the search limits how often it may stray from the best ordered move, and raises that limit until
+/// a solution turns up.
+pub struct IterativeDeepening {
+    pub max_discrepancies: usize,
+    pub max_nodes: usize,
+    nodes: usize,
+}
+
+impl IterativeDeepening {
+    pub fn new(max_discrepancies: usize, max_nodes: usize) -> IterativeDeepening {
+        IterativeDeepening { max_discrepancies, max_nodes, nodes: 0 }
+    }
+
+    /// `failed` holds the largest discrepancy budget each position has already failed with.
+    fn search(&mut self, board: &mut Board, budget: usize, failed: &mut HashMap<u64, usize>) -> Option<Vec<Move>> {
+        if board.marble_count() == 0 { return Some(Vec::new()) }
+        if failed.get(&board.hash()).map_or(false, |&b| b >= budget) || hopeless(board) { return None }
+        if self.nodes >= self.max_nodes { return None }
+        self.nodes += 1;
+
+        for (i, m) in ordered_moves(board).into_iter().enumerate() {
+            let cost = if i == 0 { 0 } else { 1 };
+            if cost > budget { break }
+
+            board.make_move(m);
+            let found = self.search(board, budget - cost, failed);
+            board.reverse_move(m);
+            if let Some(mut moves) = found {
+                moves.insert(0, m);
+                return Some(moves);
+            }
+        }
+        failed.insert(board.hash(), budget);
+        None
+    }
+}
+
+impl Solver for IterativeDeepening {
+    fn name(&self) -> &'static str { "iterative deepening" }
+
+    fn solve(&mut self, board: &Board) -> Option<Vec<Move>> {
+        self.nodes = 0;
+        let mut board = board.clone();
+        let mut failed = HashMap::new();
+        for budget in 0..self.max_discrepancies + 1 {
+            if let Some(moves) = self.search(&mut board, budget, &mut failed) {
+                return Some(moves);
+            }
+            if self.nodes >= self.max_nodes { break }
+        }
+        None
+    }
+
+    fn nodes(&self) -> usize { self.nodes }
+}
+
+/// Depth first search that tries the moves leaving the most moves open first, remembering positions
+/// that failed. Moves into positions `hopeless` rules out aren't tried.
+pub struct MobilityDfs {
+    pub max_nodes: usize,
+    nodes: usize,
+}
+
+impl MobilityDfs {
+    pub fn new(max_nodes: usize) -> MobilityDfs {
+        MobilityDfs { max_nodes, nodes: 0 }
+    }
+
+    /// Extends `moves` to a solution, or leaves it as it was.
+    fn search(&mut self, board: &mut Board, failed: &mut HashSet<u64>, moves: &mut Vec<Move>) -> bool {
+        if board.marble_count() == 0 { return true }
+        if failed.contains(&board.hash()) || self.nodes >= self.max_nodes { return false }
+        self.nodes += 1;
+
+        let mut scored: Vec<(usize, Move)> = Vec::new();
+        for m in board.legal_moves() {
+            board.make_move(m);
+            if !failed.contains(&board.hash()) && !hopeless(board) {
+                scored.push((board.legal_moves().len(), m));
+            }
+            board.reverse_move(m);
+        }
+        scored.sort_by(|a, b| b.0.cmp(&a.0));
+
+        for (_, m) in scored {
+            board.make_move(m);
+            moves.push(m);
+            let found = self.search(board, failed, moves);
+            board.reverse_move(m);
+            if found { return true }
+            moves.pop();
+        }
+        failed.insert(board.hash());
+        false
+    }
+}
+
+impl Solver for MobilityDfs {
+    fn name(&self) -> &'static str { "mobility dfs" }
+
+    fn solve(&mut self, board: &Board) -> Option<Vec<Move>> {
+        self.nodes = 0;
+        let mut board = board.clone();
+        let mut moves = Vec::new();
+        if self.search(&mut board, &mut HashSet::new(), &mut moves) { Some(moves) } else { None }
+    }
+
+    fn nodes(&self) -> usize { self.nodes }
+}
+
+/// Depth first search in random move order, starting over with a new order whenever a run expands
+/// `nodes_per_run` positions without a solution.
+pub struct RandomRestarts {
+    pub restarts: usize,
+    pub nodes_per_run: usize,
+    rng: ThreadRng,
+    nodes: usize,
+}
+
+impl RandomRestarts {
+    pub fn new(restarts: usize, nodes_per_run: usize) -> RandomRestarts {
+        RandomRestarts { restarts, nodes_per_run, rng: thread_rng(), nodes: 0 }
+    }
+
+    fn run(&mut self, board: &mut Board, visited: &mut HashSet<u64>, limit: usize) -> Option<Vec<Move>> {
+        if board.marble_count() == 0 { return Some(Vec::new()) }
+        if self.nodes >= limit || visited.contains(&board.hash()) { return None }
+        self.nodes += 1;
+
+        let mut legal = board.legal_moves();
+        self.rng.shuffle(&mut legal);
+        for m in legal {
+            board.make_move(m);
+            let found = self.run(board, visited, limit);
+            board.reverse_move(m);
+            if let Some(mut moves) = found {
+                moves.insert(0, m);
+                return Some(moves);
+            }
+        }
+        // A run that was cut short doesn't know whether this position is lost.
+        if self.nodes < limit {
+            visited.insert(board.hash());
+        }
+        None
+    }
+}
+
+impl Solver for RandomRestarts {
+    fn name(&self) -> &'static str { "random restarts" }
+
+    fn solve(&mut self, board: &Board) -> Option<Vec<Move>> {
+        self.nodes = 0;
+        let mut board = board.clone();
+        // Lost positions stay lost, so they are shared between runs.
+        let mut visited = HashSet::new();
+        for run in 0..self.restarts {
+            let limit = (run + 1) * self.nodes_per_run;
+            if let Some(moves) = self.run(&mut board, &mut visited, limit) {
+                return Some(moves);
+            }
+        }
+        None
+    }
+
+    fn nodes(&self) -> usize { self.nodes }
+}
+
+struct Tally {
+    solved: usize,
+    nodes: usize,
+    seconds: f64,
+}
+
+fn bench<S: Solver>(solver: &mut S, deals: &[Board]) {
+    let mut tally = Tally { solved: 0, nodes: 0, seconds: 0.0 };
+    for deal in deals {
+        let start = Instant::now();
+        let solution = solver.solve(deal);
+        let elapsed = start.elapsed();
+        tally.seconds += elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+        tally.nodes += solver.nodes();
+        if let Some(moves) = solution {
+            if is_solution(deal, &moves) {
+                tally.solved += 1;
+            } else {
+                println!("{} returned an invalid solution", solver.name());
+            }
+        }
+    }
+    let n = deals.len().max(1);
+    println!("{:<20} {:>4}/{:<4} {:>12} {:>12.1} {:>10.2} {:>10.3}", solver.name(), tally.solved, deals.len(),
+        tally.nodes, tally.nodes as f64 / n as f64, tally.seconds * 1000.0, tally.seconds * 1000.0 / n as f64);
+}
+
+/// Whether playing the moves in order is legal and clears the board.
+pub fn is_solution(board: &Board, moves: &[Move]) -> bool {
+    let mut board = board.clone();
+    for &m in moves {
+        let swapped = Move { a: m.b, b: m.a };
+        if !board.legal_moves().iter().any(|&l| l == m || l == swapped) { return false }
+        board.make_move(m);
+    }
+    board.marble_count() == 0
+}
+
+fn usage() -> ! {
+    println!("usage: sigmar_bot compare [--max-nodes N] [--random N] [board files]");
+    std::process::exit(1);
+}
+
+/// Runs every solver over the same deals and prints how they did.
+pub fn compare(args: &[String]) {
+    let mut deals = Vec::new();
+    let mut max_nodes = 200_000;
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        let mut number = || -> usize { rest.next().and_then(|n| n.parse().ok()).unwrap_or_else(|| usage()) };
+        if arg == "--random" {
+            let mut rng = thread_rng();
+            deals.extend((0..number()).map(|_| random_deal(&mut rng)));
+            continue;
+        }
+        if arg == "--max-nodes" {
+            max_nodes = number();
+            continue;
+        }
+        let mut text = String::new();
+        match File::open(arg).and_then(|mut f| f.read_to_string(&mut text)) {
+            Ok(_) => match Board::from_text(&text) {
+                Ok(board) => deals.push(board),
+                Err(e) => println!("{}: {}", arg, e),
+            },
+            Err(e) => println!("{}: {}", arg, e),
+        }
+    }
+    if deals.is_empty() { usage() }
+
+    println!("{:<20} {:>9} {:>12} {:>12} {:>10} {:>10}", "solver", "solved", "nodes", "nodes/deal", "ms", "ms/deal");
+    let mut dfs = Dfs::new(None);
+    dfs.max_nodes = max_nodes;
+    bench(&mut dfs, &deals);
+    bench(&mut IterativeDeepening::new(TOTAL_MOVES, max_nodes), &deals);
+    bench(&mut MobilityDfs::new(max_nodes), &deals);
+    bench(&mut RandomRestarts::new(20, max_nodes / 20), &deals);
+}
+
+#[cfg(test)]
+mod tests {
+    use ::sigmar::*;
+    use ::solver::*;
+
+    fn small_board() -> Board {
+        Board::from_text("
+                 . . . . . .
+                . . . . . . .
+               . . . . . . . .
+              . . . . . . . . .
+             . . f m a . . . . .
+            . . v . . G . . . . .
+             . . . f . w . . . .
+              . . s Q . . . . .
+               . . . L a . . .
+                . . . . . . .
+                 . . . . . .").unwrap()
+    }
+
+    fn check<S: Solver>(mut solver: S) {
+        let board = small_board();
+        let moves = solver.solve(&board).expect(solver.name());
+        assert!(is_solution(&board, &moves), "{} {:?}", solver.name(), moves);
+        assert!(solver.nodes() > 0);
+    }
+
+    #[test]
+    fn test_solvers_clear_small_board() {
+        check(Dfs::new(None));
+        check(IterativeDeepening::new(TOTAL_MOVES, 10_000));
+        check(MobilityDfs::new(10_000));
+        check(RandomRestarts::new(5, 1_000));
+    }
+}
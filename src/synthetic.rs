@@ -0,0 +1,315 @@
+use std::fs::File;
+use std::io::Write;
+use image::{ImageBuffer, Rgb};
+use rand::{thread_rng, Rng};
+
+use designer::random_deal;
+use ocr::{cell_centres, scaled_font, Frame, Image, PixelFormat, FONT, GOLD_PIXEL_VALUES, SRGB, TILE_HEIGHT, TILE_WIDTH};
+use sigmar::{Board, Marble};
+
+/// Where to draw the board and how to spoil the picture.
+#[derive(Clone)]
+pub struct Scene {
+    pub width: usize,
+    pub height: usize,
+    /// Board centre in pixels.
+    pub centre: (i32, i32),
+    /// Size of the board relative to 1080p.
+    pub scale: f32,
+    /// Standard deviation of the noise added to each channel.
+    pub noise: f32,
+    /// Radius of a box blur over the whole frame, 0 for none.
+    pub blur: usize,
+    /// Factor every channel is multiplied by.
+    pub brightness: f32,
+    /// Tip of a mouse cursor drawn over the board.
+    pub cursor: Option<(i32, i32)>,
+    /// Covers the middle of the board with the banner the game shows once it's cleared.
+    pub won: bool,
+}
+
+impl Scene {
+    /// A clean 1080p frame with the board in the middle.
+    pub fn new() -> Scene {
+        Scene {
+            width: 1920, height: 1080, centre: (960, 540), scale: 1.0,
+            noise: 0.0, blur: 0, brightness: 1.0, cursor: None, won: false,
+        }
+    }
+}
+
+const BACKGROUND: SRGB = SRGB{r: 40, g: 35, b: 30};
+const TILE: SRGB = SRGB{r: 52, g: 46, b: 39};
+/// Marbles that can't be selected are drawn this much darker.
+const LOCKED_DIMMING: f32 = 0.7;
+
+fn marble_colour(marble: Marble) -> SRGB {
+    let (r, g, b) = match marble {
+        Marble::Salt => (200, 198, 188),
+        Marble::Air => (120, 170, 200),
+        Marble::Fire => (200, 90, 60),
+        Marble::Water => (70, 110, 190),
+        Marble::Earth => (110, 160, 80),
+        Marble::Lead => (90, 90, 100),
+        Marble::Tin => (150, 150, 150),
+        Marble::Iron => (140, 110, 100),
+        Marble::Copper => (190, 120, 70),
+        Marble::Silver => (190, 190, 200),
+        Marble::Gold => (176, 158, 122),
+        Marble::Mercury => (170, 175, 190),
+        Marble::Vitae => (215, 195, 150),
+        Marble::Mors => (60, 50, 60),
+        Marble::Empty => (0, 0, 0),
+    };
+    SRGB{r, g, b}
+}
+
+fn scaled(c: SRGB, f: f32) -> SRGB {
+    let s = |v: u8| (v as f32 * f).round().max(0.0).min(255.0) as u8;
+    SRGB{r: s(c.r), g: s(c.g), b: s(c.b)}
+}
+
+fn mix(a: SRGB, b: SRGB, t: f32) -> SRGB {
+    let m = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+    SRGB{r: m(a.r, b.r), g: m(a.g, b.g), b: m(a.b, b.b)}
+}
+
+/// A disc of `colour` that fades into what's under it over its outer `soft` fraction, like the shading
+/// at the edge of a marble.
+fn fill_disc(image: &mut Image<SRGB>, (cx, cy): (i32, i32), radius: f32, soft: f32, colour: SRGB) {
+    let reach = radius.ceil() as i32;
+    for y in cy - reach..cy + reach + 1 {
+        for x in cx - reach..cx + reach + 1 {
+            if x < 0 || y < 0 || x as usize >= image.w || y as usize >= image.h { continue }
+            let (dx, dy) = ((x - cx) as f32, (y - cy) as f32);
+            let d = (dx * dx + dy * dy).sqrt() / radius;
+            if d > 1.0 { continue }
+            let i = y as usize * image.w + x as usize;
+            let cover = if soft > 0.0 { ((1.0 - d) / soft).min(1.0) } else { 1.0 };
+            image.data[i] = mix(image.data[i], colour, cover);
+        }
+    }
+}
+
+/// A standard arrow cursor, rows of (start, end) columns of its white fill from the tip down.
+const CURSOR: [(i32, i32); 17] = [
+    (0, 0), (0, 1), (0, 2), (0, 3), (0, 4), (0, 5), (0, 6), (0, 7), (0, 8), (0, 9), (0, 10),
+    (0, 6), (0, 2), (3, 4), (4, 5), (5, 6), (5, 6),
+];
+
+fn draw_cursor(image: &mut Image<SRGB>, (x, y): (i32, i32)) {
+    let mut put = |px: i32, py: i32, c: SRGB| {
+        if px >= 0 && py >= 0 && (px as usize) < image.w && (py as usize) < image.h {
+            image.data[py as usize * image.w + px as usize] = c;
+        }
+    };
+    for (row, &(start, end)) in CURSOR.iter().enumerate() {
+        let py = y + row as i32;
+        put(x + start - 1, py, SRGB{r: 0, g: 0, b: 0});
+        put(x + end + 1, py, SRGB{r: 0, g: 0, b: 0});
+        for col in start..end + 1 {
+            put(x + col, py, SRGB{r: 255, g: 255, b: 255});
+        }
+    }
+}
+
+/// A dark panel with a light frame and lines of light text across the middle of the board, as the game
+/// puts up over a cleared board until a new game is started.
+fn draw_banner(image: &mut Image<SRGB>, (cx, cy): (i32, i32), (tile_w, tile_h): (f32, f32)) {
+    let (reach_x, reach_y) = ((4.5 * tile_w) as i32, tile_h as i32);
+    let frame = std::cmp::max(2, (0.05 * tile_h) as i32);
+    let (line_h, gap) = (std::cmp::max(2, (0.2 * tile_h) as i32), std::cmp::max(2, (0.12 * tile_w) as i32));
+    for y in std::cmp::max(cy - reach_y, 0)..std::cmp::min(cy + reach_y, image.h as i32) {
+        for x in std::cmp::max(cx - reach_x, 0)..std::cmp::min(cx + reach_x, image.w as i32) {
+            let (dx, dy) = (x - cx + reach_x, y - cy + reach_y);
+            let edge = dx < frame || dy < frame || dx >= 2 * reach_x - frame || dy >= 2 * reach_y - frame;
+            // Two lines of words, split where a gap falls.
+            let line = (dy - reach_y / 2).abs() < line_h / 2 || (dy - 3 * reach_y / 2).abs() < line_h / 2;
+            let word = dx > 4 * gap && dx < 2 * reach_x - 4 * gap && (dx / gap) % 7 != 0;
+            let i = y as usize * image.w + x as usize;
+            image.data[i] = if edge { SRGB{r: 200, g: 180, b: 130} }
+                else if line && word { SRGB{r: 230, g: 220, b: 200} }
+                else { mix(image.data[i], SRGB{r: 15, g: 12, b: 10}, 0.85) };
+        }
+    }
+}
+
+fn blur(image: &Image<SRGB>, radius: usize) -> Image<SRGB> {
+    let (w, h) = (image.w as i32, image.h as i32);
+    let r = radius as i32;
+    let pass = |src: &Image<SRGB>, dx: i32, dy: i32| -> Image<SRGB> {
+        let data = (0..w * h).map(|i| {
+            let (x, y) = (i % w, i / w);
+            let (mut sum, mut n) = ([0u32; 3], 0);
+            for k in -r..r + 1 {
+                let (sx, sy) = (x + k * dx, y + k * dy);
+                if sx < 0 || sy < 0 || sx >= w || sy >= h { continue }
+                let p = src.data[(sy * w + sx) as usize];
+                sum[0] += p.r as u32;
+                sum[1] += p.g as u32;
+                sum[2] += p.b as u32;
+                n += 1;
+            }
+            SRGB{r: (sum[0] / n) as u8, g: (sum[1] / n) as u8, b: (sum[2] / n) as u8}
+        }).collect();
+        Image{w: src.w, h: src.h, data}
+    };
+    pass(&pass(image, 1, 0), 0, 1)
+}
+
+/// Roughly normal noise with the given standard deviation, from the sum of uniform samples.
+fn noise<R: Rng>(rng: &mut R, std_dev: f32) -> f32 {
+    let sum: f32 = (0..4).map(|_| rng.gen::<f32>() - 0.5).sum();
+    sum * std_dev * 3.0f32.sqrt()
+}
+
+/// Draws the board in the style of the game: a tile for every cell, marbles in their colour with their
+/// glyph, those that can't be selected dimmed, and the gold marble's middle pixels as the game draws
+/// them, and the banner if the scene is won. Then spoils the picture as the scene says. Returns a BGRA frame like the screen capture's.
+pub fn render<R: Rng>(board: &Board, scene: &Scene, rng: &mut R) -> Frame {
+    let (w, h) = (scene.width, scene.height);
+    let mut image = Image{w, h, data: (0..w * h).map(|i| scaled(BACKGROUND, 0.9 + 0.2 * (i / w) as f32 / h as f32)).collect()};
+    let (tile_w, tile_h) = (TILE_WIDTH * scene.scale, TILE_HEIGHT * scene.scale);
+    let glyphs = scaled_font(&FONT, scene.scale);
+    let least_metal = board.least_metal();
+
+    let cells = cell_centres(scene.centre, (tile_w, tile_h));
+    for &(_, centre) in &cells {
+        fill_disc(&mut image, centre, 0.48 * tile_w, 0.3, TILE);
+    }
+    for &((x, y), (px, py)) in &cells {
+        let marble = board.board[y][x];
+        if marble == Marble::Empty { continue }
+        let metal = (marble as usize) <= Marble::Gold as usize;
+        let free = board.is_free(x, y) && (!metal || marble == least_metal);
+        let dimming = if free { 1.0 } else { LOCKED_DIMMING };
+        let colour = marble_colour(marble);
+        fill_disc(&mut image, (px, py), 0.42 * tile_w, 0.4, scaled(colour, dimming));
+
+        let light = colour.r as u32 + colour.g as u32 + colour.b as u32 > 500;
+        let ink = scaled(if light { SRGB{r: 30, g: 25, b: 20} } else { SRGB{r: 235, g: 230, b: 215} }, dimming);
+        let glyph = &glyphs.iter().find(|g| g.0 == marble).unwrap().1;
+        let (left, top) = (px - glyph.w as i32 / 2, py - glyph.h as i32 / 2);
+        for i in 0..glyph.data.len() {
+            if !glyph.data[i] { continue }
+            let (gx, gy) = (left + (i % glyph.w) as i32, top + (i / glyph.w) as i32);
+            if gx >= 0 && gy >= 0 && (gx as usize) < w && (gy as usize) < h {
+                image.data[gy as usize * w + gx as usize] = ink;
+            }
+        }
+    }
+    if board.board[6][6] == Marble::Gold {
+        let (cx, cy) = scene.centre;
+        for my in 0..3 {
+            for mx in 0..5 {
+                let (x, y) = ((cx + mx as i32 - 2) as usize, (cy + my as i32 - 1) as usize);
+                image.data[y * w + x] = GOLD_PIXEL_VALUES[my][mx];
+            }
+        }
+    }
+
+    if scene.won { draw_banner(&mut image, scene.centre, (tile_w, tile_h)) }
+    if let Some(tip) = scene.cursor { draw_cursor(&mut image, tip) }
+    if scene.blur > 0 { image = blur(&image, scene.blur) }
+
+    let mut frame = Vec::with_capacity(w * h * 4);
+    for p in &image.data {
+        let mut channel = |v: u8| {
+            let v = v as f32 * scene.brightness + if scene.noise > 0.0 { noise(rng, scene.noise) } else { 0.0 };
+            v.round().max(0.0).min(255.0) as u8
+        };
+        let (r, g, b) = (channel(p.r), channel(p.g), channel(p.b));
+        frame.extend_from_slice(&[b, g, r, 255]);
+    }
+    Frame::packed(frame, w, h, PixelFormat::Bgra).expect("Rendered frame doesn't fit its size.")
+}
+
+/// `render <dir> <count> [--noise N] [--blur R] [--brightness B] [--cursor]`: random deals drawn at
+/// random places and scales, as `<dir>/deal-<i>.png` with a board file `<dir>/deal-<i>.txt` giving the
+/// centre, ready for `ocr-test`.
+pub fn run(args: &[String]) {
+    let usage = || -> ! {
+        println!("usage: sigmar_bot render <dir> <count> [--noise N] [--blur R] [--brightness B] [--cursor]");
+        ::std::process::exit(1)
+    };
+    if args.len() < 2 { usage() }
+    let dir = &args[0];
+    let count: usize = args[1].parse().unwrap_or_else(|_| usage());
+    let mut template = Scene::new();
+    let mut cursor = false;
+    let mut rest = args[2..].iter();
+    while let Some(arg) = rest.next() {
+        let mut number = || -> f32 { rest.next().and_then(|n| n.parse().ok()).unwrap_or_else(|| usage()) };
+        match arg.as_str() {
+            "--noise" => template.noise = number(),
+            "--blur" => template.blur = number() as usize,
+            "--brightness" => template.brightness = number(),
+            "--cursor" => cursor = true,
+            _ => usage(),
+        }
+    }
+
+    ::std::fs::create_dir_all(dir).expect("Couldn't create output directory.");
+    let mut rng = thread_rng();
+    for i in 0..count {
+        let board = random_deal(&mut rng);
+        // A 1080p screen is 6.5 scaled tiles high above and below the centre at about 1.45.
+        let scale = rng.gen_range(0.8, 1.4);
+        let (reach_x, reach_y) = ((TILE_WIDTH * scale * 6.0) as i32, (TILE_HEIGHT * scale * 6.5) as i32);
+        let centre = (rng.gen_range(reach_x, template.width as i32 - reach_x),
+                      rng.gen_range(reach_y, template.height as i32 - reach_y));
+        let scene = Scene {
+            centre, scale,
+            cursor: if cursor { Some((centre.0 + rng.gen_range(-reach_x, reach_x), centre.1 + rng.gen_range(-reach_y, reach_y))) } else { None },
+            ..template.clone()
+        };
+        let image = render(&board, &scene, &mut rng).to_image();
+        let rgb: Vec<u8> = image.data.iter().flat_map(|p| vec![p.r, p.g, p.b]).collect();
+        let png: ImageBuffer<Rgb<u8>, _> = ImageBuffer::from_raw(scene.width as u32, scene.height as u32, rgb)
+            .expect("Couldn't convert frame into image buffer.");
+        let path = format!("{}/deal-{}", dir, i);
+        png.save(format!("{}.png", path)).expect("Couldn't save screenshot.");
+        let mut file = File::create(format!("{}.txt", path)).expect("Couldn't create board file.");
+        write!(file, "# centre {} {}\n{}", centre.0, centre.1, board.to_text()).expect("Couldn't write board file.");
+    }
+    println!("Rendered {} deals to {}", count, dir);
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, XorShiftRng};
+    use ::designer::random_deal;
+    use ::ocr::BoardReader;
+    use ::sigmar::board_cells;
+    use ::synthetic::*;
+
+    fn misreads(scene: &Scene, seed: u32) -> Option<usize> {
+        let mut rng = XorShiftRng::from_seed([seed, 1, 2, 3]);
+        let deal = random_deal(&mut rng);
+        let frame = render(&deal, scene, &mut rng);
+        let read = BoardReader::new().read_image(&frame.to_image()).ok()?;
+        assert!((scene.centre.0 - read.centre.0).abs() <= 2 && (scene.centre.1 - read.centre.1).abs() <= 2,
+                "board at {:?} found at {:?}", scene.centre, read.centre);
+        Some(board_cells().into_iter().filter(|&(x, y)| read.board.board[y][x] != deal.board[y][x]).count())
+    }
+
+    #[test]
+    fn test_clean_render_reads_back() {
+        let scene = Scene { centre: (1000, 560), ..Scene::new() };
+        for seed in 0..3 {
+            assert_eq!(Some(0), misreads(&scene, seed), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn test_spoiled_render_reads_back() {
+        let scene = Scene {
+            centre: (900, 520), scale: 1.3, noise: 2.0, brightness: 0.98, cursor: Some((930, 500)),
+            ..Scene::new()
+        };
+        for seed in 0..3 {
+            let wrong = misreads(&scene, seed).unwrap();
+            assert!(wrong <= 2, "seed {}: {} misread", seed, wrong);
+        }
+    }
+}
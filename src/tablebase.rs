@@ -0,0 +1,381 @@
+use std;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Write};
+use rand::{thread_rng, Rng};
+
+use designer::random_deal;
+use sigmar::{board_cells, Board, Marble, MarblePos, Move, MARBLE_VALUES};
+
+/// Solved endgames: every position with at most `max_marbles` marbles that the builder came across,
+/// up to rotation and reflection of the board.
+///
+/// On disk it's `SGTB`, a version byte, `max_marbles` as a byte and the entry count as a little endian
+/// u32, followed by the entries. An entry is the marble count, a (cell, marble) byte pair per marble,
+/// and a byte that is 1 for solvable positions followed by the two cells of the best move, 0 otherwise.
+/// Cells are indices into `board_cells()`.
+pub struct Tablebase {
+    pub max_marbles: usize,
+    entries: HashMap<Vec<u8>, Verdict>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Unsolvable,
+    /// Winning move, as cells of the position the verdict was looked up for.
+    Solvable(u8, u8),
+}
+
+const MAGIC: &'static [u8; 4] = b"SGTB";
+const VERSION: u8 = 1;
+/// Most marbles a position in the file can have, as `max_marbles` is a byte.
+const MAX_MARBLES: usize = 255;
+
+lazy_static! {
+    /// For each of the 12 symmetries of the board, the cell every cell is mapped to.
+    static ref SYMMETRIES: Vec<Vec<u8>> = {
+        let cells = board_cells();
+        let index = |q: i32, r: i32| -> u8 {
+            let (x, y) = ((q + 6) as usize, (r + 6) as usize);
+            cells.iter().position(|&c| c == (x, y)).unwrap() as u8
+        };
+
+        let mut ret = Vec::with_capacity(12);
+        for reflect in 0..2 {
+            for rotation in 0..6 {
+                ret.push(cells.iter().map(|&(x, y)| {
+                    let (mut q, mut r) = (x as i32 - 6, y as i32 - 6);
+                    if reflect == 1 { std::mem::swap(&mut q, &mut r) }
+                    for _ in 0..rotation {
+                        let (q2, r2) = (-r, q + r);
+                        q = q2;
+                        r = r2;
+                    }
+                    index(q, r)
+                }).collect());
+            }
+        }
+        ret
+    };
+
+    static ref CELLS: Vec<(usize, usize)> = board_cells();
+}
+
+/// The position as sorted (cell, marble) pairs in the symmetry that gives the smallest key, and that
+/// symmetry.
+fn canonical(board: &Board) -> (Vec<u8>, usize) {
+    let occupied: Vec<(usize, u8)> = CELLS.iter().enumerate()
+        .filter(|&(_, &(x, y))| board.board[y][x] != Marble::Empty)
+        .map(|(i, &(x, y))| (i, board.board[y][x] as u8))
+        .collect();
+
+    let mut best: Option<(Vec<u8>, usize)> = None;
+    for (s, symmetry) in SYMMETRIES.iter().enumerate() {
+        let mut pairs: Vec<(u8, u8)> = occupied.iter().map(|&(i, m)| (symmetry[i], m)).collect();
+        pairs.sort();
+        let key: Vec<u8> = pairs.iter().flat_map(|&(c, m)| vec![c, m]).collect();
+        if best.as_ref().map_or(true, |&(ref b, _)| key < *b) {
+            best = Some((key, s));
+        }
+    }
+    best.unwrap()
+}
+
+fn cell_index(x: usize, y: usize) -> u8 {
+    CELLS.iter().position(|&c| c == (x, y)).unwrap() as u8
+}
+
+impl Tablebase {
+    pub fn new(max_marbles: usize) -> Tablebase {
+        Tablebase { max_marbles, entries: HashMap::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// `None` if the position isn't in the table.
+    pub fn lookup(&self, board: &Board) -> Option<Verdict> {
+        if board.marble_count() > self.max_marbles { return None }
+        let (key, s) = canonical(board);
+        let unmap = |c: u8| SYMMETRIES[s].iter().position(|&t| t == c).unwrap() as u8;
+        self.entries.get(&key).map(|&verdict| match verdict {
+            Verdict::Solvable(a, b) => Verdict::Solvable(unmap(a), unmap(b)),
+            Verdict::Unsolvable => Verdict::Unsolvable,
+        })
+    }
+
+    /// The winning move for a solvable position in the table. `None` too if the table's move isn't a
+    /// legal one, as from a corrupt entry.
+    pub fn best_move(&self, board: &Board) -> Option<Move> {
+        match self.lookup(board) {
+            Some(Verdict::Solvable(a, b)) => {
+                let pos = |c: u8| {
+                    let (x, y) = CELLS[c as usize];
+                    MarblePos { x, y, marble: board.board[y][x] }
+                };
+                let (a, b) = (pos(a), pos(b));
+                board.legal_moves().into_iter().find(|m| (m.a == a && m.b == b) || (m.a == b && m.b == a))
+            },
+            _ => None,
+        }
+    }
+
+    /// Plays the table's moves to the end. `None` if the position is unsolvable or some position on
+    /// the way is missing from the table or has no legal move in it.
+    pub fn solve(&self, board: &Board) -> Option<Vec<Move>> {
+        let mut board = board.clone();
+        let mut moves = Vec::with_capacity(self.max_marbles);
+        while board.marble_count() > 0 {
+            let m = self.best_move(&board)?;
+            board.make_move(m);
+            moves.push(m);
+        }
+        Some(moves)
+    }
+
+    /// Solves the position and every position reachable from it, adding them to the table. The
+    /// position can't have more than `max_marbles` marbles.
+    pub fn add_endgame(&mut self, board: &mut Board) -> bool {
+        if board.marble_count() == 0 { return true }
+        if let Some(verdict) = self.lookup(board) {
+            return verdict != Verdict::Unsolvable;
+        }
+
+        let mut winning = None;
+        for m in board.legal_moves() {
+            board.make_move(m);
+            let solvable = self.add_endgame(board);
+            board.reverse_move(m);
+            if solvable && winning.is_none() {
+                winning = Some(m);
+            }
+        }
+
+        let (key, s) = canonical(board);
+        let cell = |p: MarblePos| SYMMETRIES[s][cell_index(p.x, p.y) as usize];
+        let verdict = match winning {
+            Some(m) => Verdict::Solvable(cell(m.a), cell(m.b)),
+            None => Verdict::Unsolvable,
+        };
+        self.entries.insert(key, verdict);
+        winning.is_some()
+    }
+
+    /// Plays `playouts` random games of each deal down to `max_marbles` marbles and adds the endgames
+    /// they reach. Games that get stuck early are backtracked, so every playout reaches an endgame if
+    /// the deal has one. Refuses a `max_marbles` that doesn't fit the file's byte.
+    pub fn build<R: Rng>(&mut self, deals: &[Board], playouts: usize, rng: &mut R) -> Result<(), String> {
+        if self.max_marbles > MAX_MARBLES {
+            return Err(format!("Tablebases can't hold positions of more than {} marbles.", MAX_MARBLES));
+        }
+        for deal in deals {
+            for _ in 0..playouts {
+                let mut visited = HashSet::new();
+                self.random_descent(&mut deal.clone(), &mut visited, rng);
+            }
+        }
+        Ok(())
+    }
+
+    fn random_descent<R: Rng>(&mut self, board: &mut Board, visited: &mut HashSet<u64>, rng: &mut R) -> bool {
+        if board.marble_count() <= self.max_marbles {
+            self.add_endgame(board);
+            return true;
+        }
+        if !visited.insert(board.hash()) { return false }
+
+        let mut legal = board.legal_moves();
+        rng.shuffle(&mut legal);
+        for m in legal {
+            board.make_move(m);
+            let reached = self.random_descent(board, visited, rng);
+            board.reverse_move(m);
+            if reached { return true }
+        }
+        false
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        if self.max_marbles > MAX_MARBLES {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "too many marbles for a tablebase file"));
+        }
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION, self.max_marbles as u8])?;
+        let n = self.entries.len() as u32;
+        out.write_all(&[n as u8, (n >> 8) as u8, (n >> 16) as u8, (n >> 24) as u8])?;
+
+        for (key, verdict) in &self.entries {
+            out.write_all(&[(key.len() / 2) as u8])?;
+            out.write_all(key)?;
+            match *verdict {
+                Verdict::Solvable(a, b) => out.write_all(&[1, a, b])?,
+                Verdict::Unsolvable => out.write_all(&[0])?,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn load(path: &str) -> io::Result<Tablebase> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let mut input = BufReader::new(File::open(path)?);
+        let mut header = [0u8; 10];
+        input.read_exact(&mut header)?;
+        if &header[..4] != MAGIC || header[4] != VERSION {
+            return Err(invalid("not a tablebase file of a known version"));
+        }
+        let n = header[6..].iter().rev().fold(0usize, |acc, &b| acc << 8 | b as usize);
+
+        let mut tablebase = Tablebase::new(header[5] as usize);
+        let mut byte = [0u8; 1];
+        for _ in 0..n {
+            input.read_exact(&mut byte)?;
+            let mut key = vec![0u8; byte[0] as usize * 2];
+            input.read_exact(&mut key)?;
+            let valid = key.chunks(2).all(|p| (p[0] as usize) < CELLS.len()
+                && MARBLE_VALUES.iter().any(|&m| m as u8 == p[1]));
+            if !valid {
+                return Err(invalid("bad cell or marble in tablebase entry"));
+            }
+
+            input.read_exact(&mut byte)?;
+            let verdict = match byte[0] {
+                0 => Verdict::Unsolvable,
+                1 => {
+                    let mut cells = [0u8; 2];
+                    input.read_exact(&mut cells)?;
+                    if cells.iter().any(|&c| c as usize >= CELLS.len()) {
+                        return Err(invalid("bad cell in tablebase verdict"));
+                    }
+                    Verdict::Solvable(cells[0], cells[1])
+                },
+                _ => return Err(invalid("bad verdict in tablebase entry")),
+            };
+            tablebase.entries.insert(key, verdict);
+        }
+        Ok(tablebase)
+    }
+}
+
+fn usage() -> ! {
+    println!("usage: sigmar_bot tablebase build <out file> <max marbles> <random deals> [playouts per deal]");
+    println!("       sigmar_bot tablebase probe <tablebase file> <board file>");
+    std::process::exit(1);
+}
+
+pub fn run(args: &[String]) {
+    let arg = |i: usize| -> &str { args.get(i).map(|a| a.as_str()).unwrap_or_else(|| usage()) };
+    let number = |i: usize| -> usize { arg(i).parse().unwrap_or_else(|_| usage()) };
+
+    match arg(0) {
+        "build" => {
+            let mut rng = thread_rng();
+            let deals: Vec<Board> = (0..number(3)).map(|_| random_deal(&mut rng)).collect();
+            let playouts = if args.len() > 4 { number(4) } else { 20 };
+
+            let mut tablebase = Tablebase::new(number(2));
+            tablebase.build(&deals, playouts, &mut rng).unwrap_or_else(|e| {
+                println!("{}", e);
+                std::process::exit(1)
+            });
+            tablebase.save(arg(1)).expect("Couldn't write tablebase.");
+            println!("Wrote {} positions to {}", tablebase.len(), arg(1));
+        },
+        "probe" => {
+            let tablebase = Tablebase::load(arg(1)).expect("Couldn't read tablebase.");
+            let mut text = String::new();
+            File::open(arg(2)).and_then(|mut f| f.read_to_string(&mut text)).expect("Couldn't read board file.");
+            let board = Board::from_text(&text).unwrap_or_else(|e| panic!("Bad board file: {}", e));
+
+            match tablebase.lookup(&board) {
+                None => println!("Position isn't in the tablebase."),
+                Some(Verdict::Unsolvable) => println!("Unsolvable."),
+                Some(_) => println!("Solvable: {:?}", tablebase.solve(&board)),
+            }
+        },
+        _ => usage(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+    use rand::{SeedableRng, XorShiftRng};
+    use ::sigmar::*;
+    use ::tablebase::*;
+
+    fn endgame() -> [[Marble; 13]; 13] {
+        let mut cells = [[Marble::Empty; 13]; 13];
+        cells[6][6] = Marble::Gold;
+        cells[6][7] = Marble::Fire;
+        cells[8][3] = Marble::Fire;
+        cells[4][8] = Marble::Salt;
+        cells[4][9] = Marble::Water;
+        cells
+    }
+
+    #[test]
+    fn test_lookup_in_rotated_position() {
+        let cells = endgame();
+        let mut tablebase = Tablebase::new(5);
+        assert!(tablebase.add_endgame(&mut Board::offline(cells)));
+
+        // 60 degrees around the centre
+        let mut rotated = [[Marble::Empty; 13]; 13];
+        for (x, y) in board_cells() {
+            let (q, r) = (x as i32 - 6, y as i32 - 6);
+            rotated[(q + r + 6) as usize][(6 - r) as usize] = cells[y][x];
+        }
+        let mut board = Board::offline(rotated);
+        let moves = tablebase.solve(&board).unwrap();
+        assert_eq!(3, moves.len());
+        for m in moves {
+            let swapped = Move { a: m.b, b: m.a };
+            assert!(board.legal_moves().iter().any(|&l| l == m || l == swapped));
+            board.make_move(m);
+        }
+        assert_eq!(0, board.marble_count());
+    }
+
+    #[test]
+    fn test_load_rejects_corrupt_file() {
+        let board = Board::offline(endgame());
+        let mut tablebase = Tablebase::new(5);
+        tablebase.entries.insert(canonical(&board).0, Verdict::Solvable(0, 1));
+        let path = env::temp_dir().join("sigmar-tablebase-test.bin");
+        let path = path.to_str().unwrap();
+        tablebase.save(path).unwrap();
+        assert_eq!(tablebase.lookup(&board), Tablebase::load(path).unwrap().lookup(&board));
+
+        // A file of one solvable entry ends in the two cells of its move.
+        let bytes = fs::read(path).unwrap();
+        let mut corrupt = bytes.clone();
+        let last = corrupt.len() - 1;
+        corrupt[last] = CELLS.len() as u8;
+        fs::write(path, &corrupt).unwrap();
+        assert!(Tablebase::load(path).is_err());
+        fs::write(path, &bytes[..last]).unwrap();
+        assert!(Tablebase::load(path).is_err());
+        // The verdict byte comes before the move.
+        let mut corrupt = bytes.clone();
+        corrupt[last - 2] = 2;
+        fs::write(path, &corrupt).unwrap();
+        assert!(Tablebase::load(path).is_err());
+        fs::remove_file(path).unwrap();
+
+        let mut huge = Tablebase::new(MAX_MARBLES + 1);
+        assert!(huge.build(&[], 1, &mut XorShiftRng::from_seed([1, 2, 3, 4])).is_err());
+    }
+
+    #[test]
+    fn test_illegal_table_move_is_no_move() {
+        // The move isn't a legal pair: playing it would leave the board as it was.
+        let board = Board::offline(endgame());
+        let mut tablebase = Tablebase::new(5);
+        tablebase.entries.insert(canonical(&board).0, Verdict::Solvable(0, 1));
+        assert_eq!(None, tablebase.best_move(&board));
+        assert!(tablebase.solve(&board).is_none());
+    }
+}